@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+
+use crate::LRUCache;
+
+/// Builds an `LRUCache` step by step; see `LRUCache::builder`.
+#[derive(Debug, Clone)]
+pub struct LRUCacheBuilder<K, V> {
+    capacity: Option<usize>,
+    min_capacity: usize,
+    max_capacity: usize,
+    entries: Vec<(K, V)>,
+}
+
+/// The reasons `LRUCacheBuilder::build` can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+    /// The capacity wasn't set.
+    MissingCapacity,
+    /// The capacity doesn't lie within the minimum and maximum capacity.
+    CapacityOutOfRange { min: usize, capacity: usize, max: usize },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::MissingCapacity => write!(f, "the capacity of the cache must be set"),
+            BuildError::CapacityOutOfRange { min, capacity, max } => write!(
+                f,
+                "capacity {} is outside of the allowed range {}..={}",
+                capacity, min, max
+            ),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+impl<K, V> LRUCacheBuilder<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    pub(crate) fn new() -> Self {
+        LRUCacheBuilder {
+            capacity: None,
+            min_capacity: 0,
+            max_capacity: usize::MAX,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Sets the capacity of the cache. This is required.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets the smallest capacity `resize` can shrink the cache to.
+    pub fn min_capacity(mut self, min_capacity: usize) -> Self {
+        self.min_capacity = min_capacity;
+        self
+    }
+
+    /// Sets the largest capacity `resize` can grow the cache to.
+    pub fn max_capacity(mut self, max_capacity: usize) -> Self {
+        self.max_capacity = max_capacity;
+        self
+    }
+
+    /// Sets items the cache starts out with. They are inserted in order,
+    /// so the last one is the most recently used, and only the last ones
+    /// survive if there are more than the capacity.
+    pub fn entries<I: IntoIterator<Item = (K, V)>>(mut self, entries: I) -> Self {
+        self.entries.extend(entries);
+        self
+    }
+
+    /// Creates the cache, checking that the capacity was set and lies
+    /// within the minimum and maximum capacity.
+    pub fn build(self) -> Result<LRUCache<K, V>, BuildError> {
+        let capacity = self.capacity.ok_or(BuildError::MissingCapacity)?;
+        if !(self.min_capacity..=self.max_capacity).contains(&capacity) {
+            return Err(BuildError::CapacityOutOfRange {
+                min: self.min_capacity,
+                capacity,
+                max: self.max_capacity,
+            });
+        }
+        let mut cache = LRUCache::from_iter_with_capacity(capacity, self.entries);
+        cache.min_capacity = self.min_capacity;
+        cache.max_capacity = self.max_capacity;
+        Ok(cache)
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::time::Instant;
+
+use crate::LRUCache;
+
+/// A copy of the contents of a cache at some point; see
+/// `LRUCache::checkpoint`.
+#[derive(Debug, Clone)]
+pub struct Checkpoint<K, V> {
+    // The items from the most to the least recently used.
+    entries: Vec<(K, V)>,
+    // When each item was inserted, and when it expires if it does.
+    inserted_at: HashMap<K, Instant>,
+    expires_at: HashMap<K, Instant>,
+}
+
+/// What changed in a cache since a checkpoint; see `LRUCache::diff_since`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheDiff<K: Eq + Hash> {
+    /// Keys that are in the cache but weren't in the checkpoint.
+    pub added: HashSet<K>,
+    /// Keys that were in the checkpoint but aren't in the cache.
+    pub removed: HashSet<K>,
+    /// Keys that are in both, with different values.
+    pub modified: HashSet<K>,
+}
+
+impl<K, V> LRUCache<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+    V: Clone,
+{
+    /// Copies the items of the cache, their recency order and their
+    /// timestamps, to go back to with `rollback` or compare against with
+    /// `diff_since`. Like with `peek`, stale and expired items count as
+    /// absent.
+    pub fn checkpoint(&self) -> Checkpoint<K, V> {
+        let entries: Vec<(K, V)> = self
+            .order
+            .iter()
+            .filter_map(|&key| self.peek(key).map(|value| (key, value.clone())))
+            .collect();
+        let copy_times = |times: &HashMap<K, Instant>| {
+            entries
+                .iter()
+                .filter_map(|(key, _)| times.get(key).map(|&time| (*key, time)))
+                .collect()
+        };
+        Checkpoint {
+            inserted_at: copy_times(&self.inserted_at),
+            expires_at: copy_times(&self.expires_at),
+            entries,
+        }
+    }
+
+    /// Replaces the contents of the cache with those of `cp`, in the same
+    /// recency order. The items keep the insertion times and expiry they
+    /// had, so an item whose time-to-live ran out since counts as expired.
+    /// Sampled admission doesn't apply and nothing counts as evicted; if
+    /// the capacity shrank in the meantime, only the most recently used
+    /// items that fit are restored.
+    pub fn rollback(&mut self, cp: &Checkpoint<K, V>) {
+        self.clear();
+        for (key, value) in cp.entries.iter().take(self.capacity) {
+            self.store(*key, value.clone());
+            self.order.push_back(*key);
+            if let Some(&inserted_at) = cp.inserted_at.get(key) {
+                self.inserted_at.insert(*key, inserted_at);
+            }
+            match cp.expires_at.get(key) {
+                Some(&expires_at) => self.expires_at.insert(*key, expires_at),
+                None => self.expires_at.remove(key),
+            };
+        }
+    }
+
+    /// Lists the keys that were added, removed, or given a different value
+    /// since `cp` was taken. Changes in recency alone aren't reported.
+    pub fn diff_since(&self, cp: &Checkpoint<K, V>) -> CacheDiff<K>
+    where
+        V: PartialEq,
+    {
+        let mut diff = CacheDiff {
+            added: self.storage.keys().filter(|&&key| self.peek(key).is_some()).copied().collect(),
+            removed: HashSet::new(),
+            modified: HashSet::new(),
+        };
+        for (key, value) in &cp.entries {
+            diff.added.remove(key);
+            match self.peek(*key) {
+                None => {
+                    diff.removed.insert(*key);
+                }
+                Some(current) if current != value => {
+                    diff.modified.insert(*key);
+                }
+                Some(_) => {}
+            }
+        }
+        diff
+    }
+}
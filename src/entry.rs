@@ -0,0 +1,134 @@
+use std::hash::Hash;
+use std::time::Duration;
+
+use crate::{InsertPos, LRUCache};
+
+/// A view into a single item of the cache, which may or may not be there.
+/// Returned by `LRUCache::entry`.
+#[derive(Debug)]
+pub enum Entry<'a, K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    /// The key is in the cache.
+    Occupied(OccupiedEntry<'a, K, V>),
+    /// The key isn't in the cache.
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// An entry whose key is in the cache.
+#[derive(Debug)]
+pub struct OccupiedEntry<'a, K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    pub(crate) cache: &'a mut LRUCache<K, V>,
+    pub(crate) key: K,
+}
+
+/// An entry whose key isn't in the cache.
+#[derive(Debug)]
+pub struct VacantEntry<'a, K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    pub(crate) cache: &'a mut LRUCache<K, V>,
+    pub(crate) key: K,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Returns the value, inserting `default` first if the key is vacant.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Returns the value, inserting the result of `f` first
+    /// if the key is vacant.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(f()),
+        }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns a reference to the value.
+    pub fn get(&self) -> &V {
+        &self.cache.storage[&self.key]
+    }
+
+    /// Returns a mutable reference to the value.
+    pub fn get_mut(&mut self) -> &mut V {
+        self.cache.storage.get_mut(&self.key).unwrap()
+    }
+
+    /// Returns a mutable reference to the value that lives as long as
+    /// the borrow of the cache.
+    pub fn into_mut(self) -> &'a mut V {
+        self.cache.storage.get_mut(&self.key).unwrap()
+    }
+
+    /// Replaces the value, returning the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        let old = self.cache.store(self.key, value).unwrap();
+        self.cache.evict_overweight(self.key);
+        old
+    }
+
+    /// Removes the item from the cache, returning its value.
+    pub fn remove(self) -> V {
+        self.cache.remove_entry(self.key).unwrap()
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Gives back the key without inserting anything.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts `value` as the most recently used item, evicting the least
+    /// recently used one if the cache is full. Sampled admission doesn't
+    /// apply here.
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.cache.insert_new(self.key, value, InsertPos::Front);
+        self.cache.storage.get_mut(&self.key).unwrap()
+    }
+
+    /// Like `insert`, but the item expires after `ttl`
+    /// instead of the cache-wide time-to-live.
+    pub fn insert_with_ttl(self, value: V, ttl: Duration) -> &'a mut V {
+        self.cache.insert_new(self.key, value, InsertPos::Front);
+        let expires_at = self.cache.clock.now() + ttl;
+        self.cache.expires_at.insert(self.key, expires_at);
+        self.cache.storage.get_mut(&self.key).unwrap()
+    }
+}
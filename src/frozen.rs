@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::hasher::CacheHasher;
+use crate::LRUCache;
+
+/// A read-only snapshot of a cache; see `LRUCache::into_frozen`.
+/// Lookups don't track recency, so they only need `&self`.
+#[derive(Debug, Clone)]
+pub struct FrozenCache<K, V> {
+    storage: HashMap<K, V, CacheHasher>,
+    // The keys from the most to the least recently used, at freezing time.
+    order: Vec<K>,
+}
+
+impl<K, V> FrozenCache<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    /// Returns a reference to the value corresponding to the `key`.
+    pub fn get(&self, key: K) -> Option<&V> {
+        self.storage.get(&key)
+    }
+
+    /// Returns whether the `key` is in the snapshot.
+    pub fn contains_key(&self, key: K) -> bool {
+        self.storage.contains_key(&key)
+    }
+
+    /// Returns the number of items.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Returns whether there are no items.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Returns an iterator over the items, from the most to the least
+    /// recently used at the time the cache was frozen.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.order.iter().map(move |key| (key, &self.storage[key]))
+    }
+}
+
+impl<K, V> LRUCache<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    /// Turns the cache into a read-only snapshot of its items and their
+    /// recency order. Stale and expired items are left out. The items are
+    /// moved, not dropped, so the drop flush callback doesn't see them.
+    pub fn into_frozen(mut self) -> FrozenCache<K, V> {
+        let stale: Vec<K> = self
+            .order
+            .iter()
+            .copied()
+            .filter(|&key| self.is_stale(key))
+            .collect();
+        for key in stale {
+            self.remove_entry(key);
+        }
+        let storage = std::mem::take(&mut self.storage);
+        let order = std::mem::take(&mut self.order)
+            .into_iter()
+            .filter(|key| storage.contains_key(key))
+            .collect();
+        FrozenCache { storage, order }
+    }
+}
@@ -0,0 +1,31 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hasher};
+
+/// Builds the hashers for the storage of a cache: randomly keyed by
+/// default, or the same on every run for `LRUCache::with_seed`.
+#[derive(Debug, Clone)]
+pub(crate) enum CacheHasher {
+    Random(RandomState),
+    Seeded(u64),
+}
+
+impl Default for CacheHasher {
+    fn default() -> Self {
+        CacheHasher::Random(RandomState::new())
+    }
+}
+
+impl BuildHasher for CacheHasher {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        match self {
+            CacheHasher::Random(state) => state.build_hasher(),
+            CacheHasher::Seeded(seed) => {
+                let mut hasher = DefaultHasher::new();
+                hasher.write_u64(*seed);
+                hasher
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+/// A user callback stored in the cache.
+/// Clones of a cache start out without callbacks, since boxed closures
+/// can't be cloned, and a callback firing for two caches would be
+/// surprising anyway.
+pub(crate) struct Hook<F: ?Sized>(Option<Box<F>>);
+
+impl<F: ?Sized> Hook<F> {
+    pub(crate) fn set(&mut self, f: Box<F>) {
+        self.0 = Some(f);
+    }
+
+    pub(crate) fn get_mut(&mut self) -> Option<&mut F> {
+        self.0.as_deref_mut()
+    }
+}
+
+impl<F: ?Sized> Default for Hook<F> {
+    fn default() -> Self {
+        Hook(None)
+    }
+}
+
+impl<F: ?Sized> Clone for Hook<F> {
+    fn clone(&self) -> Self {
+        Hook(None)
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Some(..)" } else { "None" })
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::LRUCache;
+
+/// Updates the pool of `insert_interned` when an item leaves the cache.
+pub(crate) type Release<V> = fn(&mut HashMap<V, usize>, &V);
+
+impl<K, T> LRUCache<K, Rc<T>>
+where
+    K: Eq + PartialEq + Copy + Hash,
+    T: Eq + Hash,
+{
+    /// Like `insert`, but items with equal values share a single
+    /// allocation: if an equal value was already inserted this way, the
+    /// new item points to it and `value` is dropped. A pooled value is
+    /// released once the last item pointing to it leaves the cache.
+    ///
+    /// Returns the previous value of the `key`, if any.
+    pub fn insert_interned(&mut self, key: K, value: T) -> Option<Rc<T>> {
+        self.release_interned = Some(release::<T>);
+        let pooled = match self.interned.get_key_value(&value) {
+            Some((pooled, _)) => Rc::clone(pooled),
+            None => Rc::new(value),
+        };
+        *self.interned.entry(Rc::clone(&pooled)).or_insert(0) += 1;
+
+        let old = self.storage.get(&key).cloned();
+        if self.insert(key, Rc::clone(&pooled)).is_none() {
+            // Turned away by sampled admission.
+            release(&mut self.interned, &pooled);
+        }
+        old
+    }
+}
+
+// Forgets one item pointing to `value`, if it came from the pool.
+fn release<T: Eq + Hash>(pool: &mut HashMap<Rc<T>, usize>, value: &Rc<T>) {
+    match pool.get_key_value(value) {
+        Some((pooled, _)) if Rc::ptr_eq(pooled, value) => {}
+        _ => return,
+    }
+    if let Some(count) = pool.get_mut(value) {
+        *count -= 1;
+        if *count == 0 {
+            pool.remove(value);
+        }
+    }
+}
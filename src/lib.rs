@@ -1,16 +1,155 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::LinkedList;
+use std::fmt::{self, Write};
 use std::hash::Hash;
+use std::ops::{Add, Range};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod builder;
+mod checkpoint;
+mod clock;
+mod entry;
+mod frozen;
+mod hasher;
+mod hook;
+mod intern;
+mod meta;
+mod rng;
+mod sharded;
+mod slru;
+mod stats;
+
+pub use builder::{BuildError, LRUCacheBuilder};
+pub use checkpoint::{CacheDiff, Checkpoint};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use frozen::FrozenCache;
+pub use rng::{Rng, SplitMix64};
+pub use sharded::ShardedCache;
+pub use slru::SLRUCache;
+pub use stats::{CacheStats, StatsDelta, StatsSnapshot};
+
+use hasher::CacheHasher;
+use hook::Hook;
+use meta::MetaMap;
+use stats::{ReuseTracker, SegmentStats};
+
+/// Where a newly inserted item lands in the recency order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertPos {
+    /// The most recently used end.
+    Front,
+    /// Halfway between the two ends.
+    Middle,
+    /// The least recently used end, i.e. the next item to be evicted.
+    Back,
+}
+
+/// Computes the weight of an item for weight-bounded caches.
+pub type Weigher<K, V> = fn(&K, &V) -> usize;
+
+// Turns values into bytes; see `LRUCache::set_value_serializer`.
+type Serializer<V> = dyn Fn(&V) -> Vec<u8> + Send;
+
+/// How a cache limits its size; see `LRUCache::bounded_by`.
+#[derive(Debug, Clone)]
+pub enum Bound<K, V> {
+    /// At most this many items.
+    Count(usize),
+    /// At most this total weight, as computed by the weigher.
+    Weight(usize, Weigher<K, V>),
+    /// At most this many bytes, counting the in-place size of each key
+    /// and value. Heap memory owned by them is not counted.
+    Bytes(usize),
+}
+
+fn size_of_item<K, V>(_: &K, _: &V) -> usize {
+    std::mem::size_of::<K>() + std::mem::size_of::<V>()
+}
+
+/// Whether a full cache grows instead of evicting; see
+/// `LRUCache::with_growth_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GrowthPolicy {
+    /// Never grow, always evict.
+    #[default]
+    Fixed,
+    /// Double the capacity, up to `max`.
+    Double { max: usize },
+    /// Add `step` to the capacity, up to `max`.
+    Linear { step: usize, max: usize },
+}
+
+impl GrowthPolicy {
+    // The capacity to grow to from `capacity`, at most `limit`, if any.
+    fn grow(self, capacity: usize, limit: usize) -> Option<usize> {
+        let grown = match self {
+            GrowthPolicy::Fixed => return None,
+            GrowthPolicy::Double { max } => capacity.saturating_mul(2).max(1).min(max),
+            GrowthPolicy::Linear { step, max } => capacity.saturating_add(step).min(max),
+        };
+        let grown = grown.min(limit);
+        (grown > capacity).then_some(grown)
+    }
+}
+
+/// What `LRUCache::maintenance` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MaintenanceReport {
+    /// Items removed because their time-to-live ran out.
+    pub expired: usize,
+    /// Items removed because they had been marked stale by `invalidate`.
+    pub invalidated: usize,
+}
 
 #[derive(Debug, Clone)]
 /// An LRU cache using hashmap and doubly-linked list.
+///
+/// The front of the list is the most recently used item (recency rank 0)
+/// and the back is the least recently used one, which is evicted next.
+/// Everything that lists items goes from the front to the back unless its
+/// docs say otherwise.
 pub struct LRUCache<K, V>
 where
     K: Eq + PartialEq + Copy + Hash,
 {
-    storage: HashMap<K, V>,
+    storage: HashMap<K, V, CacheHasher>,
     order: LinkedList<K>,
     capacity: usize,
+    min_capacity: usize,
+    max_capacity: usize,
+    shrink_on_clear: bool,
+    freeze_recency: bool,
+    growth_policy: GrowthPolicy,
+    poisoned: HashSet<K>,
+    ops: u64,
+    resize_cooldown: u64,
+    last_resize: Option<u64>,
+    weigher: Option<Weigher<K, V>>,
+    max_weight: usize,
+    total_weight: usize,
+    weights: HashMap<K, usize>,
+    last_evicted: Option<(K, V)>,
+    clock: Arc<dyn Clock>,
+    inserted_at: HashMap<K, Instant>,
+    ttl: Option<Duration>,
+    expires_at: HashMap<K, Instant>,
+    eager_expire: usize,
+    admission_probability: f64,
+    rng: SplitMix64,
+    interned: HashMap<V, usize>,
+    release_interned: Option<intern::Release<V>>,
+    on_miss: Hook<dyn FnMut(&K) + Send>,
+    on_drop_flush: Hook<dyn FnMut(K, V) + Send>,
+    value_serializer: Hook<Serializer<V>>,
+    on_evict_serialized: Hook<dyn FnMut(K, Vec<u8>) + Send>,
+    stats: CacheStats,
+    reuse: Option<ReuseTracker<K>>,
+    access_counts: Option<HashMap<K, u64>>,
+    meta: MetaMap<K>,
+    pending: HashSet<K>,
+    segments: SegmentStats<K>,
 }
 
 impl<K, V> LRUCache<K, V>
@@ -20,199 +159,3922 @@ where
     /// Creates a new `LRUCache` with specified capacity.
     /// Capacity is always a positive number.
     pub fn new(capacity: usize) -> Self {
+        Self::with_bounds(capacity, None, usize::MAX)
+    }
+
+    /// Like `new`, but hashes keys the same way on every run and seeds
+    /// sampled admission with `seed`, so that the same operations always
+    /// leave the cache in the same state. Meant for tests; `new` keeps
+    /// hashing randomized, which protects against crafted keys.
+    pub fn with_seed(capacity: usize, seed: u64) -> Self {
+        let mut cache = Self::new(capacity);
+        let preallocated = cache.storage.capacity();
+        cache.storage = HashMap::with_capacity_and_hasher(preallocated, CacheHasher::Seeded(seed));
+        cache.rng = SplitMix64::new(seed);
+        cache
+    }
+
+    /// Creates a new `LRUCache` bounded according to `bound`.
+    ///
+    /// With `Bound::Count` this is the same as `new`. The other bounds
+    /// don't limit the number of items, only their total weight: LRU items
+    /// are evicted until a new item fits. An item heavier than the whole
+    /// budget evicts everything else and is kept on its own.
+    pub fn bounded_by(bound: Bound<K, V>) -> Self {
+        match bound {
+            Bound::Count(capacity) => Self::new(capacity),
+            Bound::Weight(max_weight, weigher) => {
+                Self::with_bounds(usize::MAX, Some(weigher), max_weight)
+            }
+            Bound::Bytes(max_bytes) => {
+                Self::with_bounds(usize::MAX, Some(size_of_item::<K, V>), max_bytes)
+            }
+        }
+    }
+
+    fn with_bounds(capacity: usize, weigher: Option<Weigher<K, V>>, max_weight: usize) -> Self {
+        // Weight-bounded caches don't limit the count,
+        // so there's nothing sensible to preallocate.
+        let storage = if capacity == usize::MAX {
+            HashMap::default()
+        } else {
+            HashMap::with_capacity_and_hasher(capacity, CacheHasher::default())
+        };
         LRUCache {
-            storage: HashMap::with_capacity(capacity),
+            storage,
             order: LinkedList::new(),
             capacity,
+            min_capacity: 0,
+            max_capacity: usize::MAX,
+            shrink_on_clear: false,
+            freeze_recency: false,
+            growth_policy: GrowthPolicy::Fixed,
+            poisoned: HashSet::new(),
+            ops: 0,
+            resize_cooldown: 0,
+            last_resize: None,
+            weigher,
+            max_weight,
+            total_weight: 0,
+            weights: HashMap::new(),
+            last_evicted: None,
+            clock: Arc::new(SystemClock),
+            inserted_at: HashMap::new(),
+            ttl: None,
+            expires_at: HashMap::new(),
+            eager_expire: 0,
+            admission_probability: 1.0,
+            rng: SplitMix64::from_entropy(),
+            interned: HashMap::new(),
+            release_interned: None,
+            on_miss: Hook::default(),
+            on_drop_flush: Hook::default(),
+            value_serializer: Hook::default(),
+            on_evict_serialized: Hook::default(),
+            stats: CacheStats::default(),
+            reuse: None,
+            access_counts: None,
+            meta: MetaMap::default(),
+            pending: HashSet::new(),
+            segments: SegmentStats::default(),
+        }
+    }
+
+    /// Returns a builder for configuring a new `LRUCache`.
+    pub fn builder() -> LRUCacheBuilder<K, V> {
+        LRUCacheBuilder::new()
+    }
+
+    /// Creates a new `LRUCache` with specified capacity and fills it
+    /// from `iter`. Items are inserted in iteration order, so when there
+    /// are more than `capacity` of them only the last ones survive,
+    /// with the very last one as the most recently used.
+    pub fn from_iter_with_capacity<I: IntoIterator<Item = (K, V)>>(
+        capacity: usize,
+        iter: I,
+    ) -> Self {
+        let mut cache = LRUCache::new(capacity);
+        for (key, value) in iter {
+            cache.insert(key, value);
+        }
+        cache
+    }
+
+    /// Returns the number of items in the cache.
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// Returns whether the cache holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+
+    /// Returns the maximum number of items the cache can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns `true` if the cache holds as many items as its capacity,
+    /// so inserting a new key evicts the least recently used one. Slots
+    /// reserved with `prefetch` count toward the capacity.
+    pub fn is_full(&self) -> bool {
+        self.order.len() >= self.capacity
+    }
+
+    /// Returns the total weight of the items in a cache created with
+    /// `Bound::Weight` or `Bound::Bytes`, and 0 otherwise.
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    /// Returns the hit, miss and eviction counters. Lookups are counted by
+    /// `get` and the `get_or_*` methods, evictions whenever an item is
+    /// evicted to make room.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Turns on hit and miss counters per segment of the keys, where
+    /// `segment_of` tells the segment of a key, e.g. its tenant; see
+    /// `segmented_stats`. Clones of the cache keep the counters so far,
+    /// but don't count any further.
+    pub fn with_segmented_stats<S, F>(mut self, segment_of: F) -> Self
+    where
+        S: Eq + Hash + Clone + Send + 'static,
+        F: Fn(&K) -> S + Send + 'static,
+    {
+        self.segments.set(segment_of);
+        self
+    }
+
+    /// Returns the hit and miss counters of each segment that was looked
+    /// up. Evictions aren't broken down, so they are always 0 here. Empty
+    /// unless `with_segmented_stats` was used with segments of type `S`.
+    pub fn segmented_stats<S>(&self) -> HashMap<S, CacheStats>
+    where
+        S: Eq + Hash + Clone + 'static,
+    {
+        self.segments.get().cloned().unwrap_or_default()
+    }
+
+    /// Returns the current stats along with the time, for computing what
+    /// happened in an interval with `StatsSnapshot::since`.
+    pub fn stats_snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            stats: self.stats,
+            taken_at: self.clock.now(),
+        }
+    }
+
+    /// Describes the whole state of the cache for logs: a header with the
+    /// size, load factor and stats, then one line per item with its recency
+    /// rank, key, value and age, from the most to the least recently used.
+    pub fn debug_dump(&self) -> String
+    where
+        K: fmt::Debug,
+        V: fmt::Debug,
+    {
+        let len = self.storage.len();
+        let load = if self.capacity == usize::MAX || self.capacity == 0 {
+            0.0
+        } else {
+            len as f64 / self.capacity as f64
+        };
+        let now = self.clock.now();
+        let mut dump = String::new();
+        // Writing to a String can't fail.
+        let _ = writeln!(dump, "capacity: {}", self.capacity);
+        let _ = writeln!(dump, "len: {len}");
+        let _ = writeln!(dump, "load factor: {load:.2}");
+        let _ = writeln!(
+            dump,
+            "stats: {} hits, {} misses, {} evictions",
+            self.stats.hits, self.stats.misses, self.stats.evictions
+        );
+        for (rank, (key, value)) in self.iter().enumerate() {
+            let age = self
+                .inserted_at
+                .get(key)
+                .map(|&at| now.saturating_duration_since(at))
+                .unwrap_or_default();
+            let _ = writeln!(dump, "#{rank} {key:?} => {value:?} (age {age:?})");
+        }
+        dump
+    }
+
+    /// Suggests a capacity based on the stats.
+    ///
+    /// The heuristic is deliberately simple: if fewer than half of the
+    /// lookups hit while items are being evicted, the cache is thrashing,
+    /// i.e. items are evicted before they get reused, so twice the current
+    /// capacity is suggested (within the builder's `max_capacity`). With a
+    /// good hit ratio, no evictions, or fewer lookups than the capacity to
+    /// judge by, the current capacity is returned.
+    pub fn recommend_capacity(&self) -> usize {
+        let lookups = self.stats.hits + self.stats.misses;
+        if lookups < self.capacity as u64 || self.stats.evictions == 0 {
+            return self.capacity;
+        }
+        if self.stats.hit_ratio() >= 0.5 {
+            return self.capacity;
+        }
+        self.capacity.saturating_mul(2).min(self.max_capacity)
+    }
+
+    /// Changes the capacity of the cache, evicting LRU items if it shrinks
+    /// below the current length. The new capacity is clamped to the range
+    /// set with the builder's `min_capacity` and `max_capacity`.
+    ///
+    /// Returns `false` if the capacity didn't change, either because it is
+    /// the same or because the resize cooldown hasn't elapsed yet.
+    pub fn resize(&mut self, capacity: usize) -> bool {
+        let capacity = capacity.clamp(self.min_capacity, self.max_capacity);
+        if capacity == self.capacity {
+            return false;
         }
+        if let Some(last) = self.last_resize {
+            if self.ops - last < self.resize_cooldown {
+                return false;
+            }
+        }
+        self.capacity = capacity;
+        while self.order.len() > self.capacity {
+            self.evict_for_room();
+        }
+        self.last_resize = Some(self.ops);
+        true
+    }
+
+    /// Sets the minimum number of operations (`insert`s and `get`s) that
+    /// must happen between two capacity changes. Resizes requested sooner
+    /// are ignored. Defaults to 0, i.e. no cooldown.
+    pub fn set_resize_cooldown(&mut self, ops: u64) {
+        self.resize_cooldown = ops;
+    }
+
+    /// Replaces the clock used to timestamp insertions.
+    /// Meant to be called on an empty cache, typically with a `MockClock`.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Sets the time-to-live of items inserted from now on.
+    /// An expired item is treated as absent by `get` and `peek`, and the
+    /// next `get` removes it. `None`, the default, means items never expire.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.ttl = ttl;
+    }
+
+    /// Makes every `insert` first look at up to `max_checks` of the least
+    /// recently used items and remove those that have expired, so expired
+    /// items don't pile up between lookups. Defaults to 0, i.e. expired
+    /// items are only removed lazily.
+    pub fn set_eager_expire(&mut self, max_checks: usize) {
+        self.eager_expire = max_checks;
+    }
+
+    /// Makes `insert` admit a key that isn't in the cache yet only with
+    /// probability `p`, clamped to `[0, 1]`. Skipping most one-off keys
+    /// keeps them from pushing out useful items under write-heavy load.
+    /// Updates of existing keys are always applied. Defaults to 1.
+    pub fn set_admission_probability(&mut self, p: f64) {
+        self.admission_probability = p.clamp(0.0, 1.0);
+    }
+
+    /// Seeds the random number generator behind sampled admission,
+    /// which is otherwise seeded randomly. Useful for reproducible tests.
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng = SplitMix64::new(seed);
+    }
+
+    /// Sets a callback that is called with the key whenever a lookup
+    /// misses: in `get`, and in the `get_or_*` methods before the value is
+    /// computed. It isn't called on hits. Clones of the cache don't keep it.
+    pub fn set_on_miss<F: FnMut(&K) + Send + 'static>(&mut self, f: F) {
+        self.on_miss.set(Box::new(f));
+    }
+
+    /// Lets the cache start small and grow with demand: when a new key
+    /// would evict an item, `policy` decides whether to grow the capacity
+    /// instead. Growth never goes past the builder's `max_capacity`.
+    pub fn with_growth_policy(mut self, policy: GrowthPolicy) -> Self {
+        self.growth_policy = policy;
+        self
+    }
+
+    /// Turns on tracking of reuse distances for `reuse_histogram`.
+    ///
+    /// This remembers every key ever looked up, including evicted ones,
+    /// so it is meant for analysis runs rather than production use.
+    pub fn with_reuse_tracking(mut self) -> Self {
+        self.reuse = Some(ReuseTracker::new());
+        self
+    }
+
+    /// Turns on counting how often each item is found by a lookup; see
+    /// `access_count` and `reorder_by_access_count`.
+    pub fn with_access_counts(mut self) -> Self {
+        self.access_counts = Some(HashMap::new());
+        self
+    }
+
+    /// Returns how often the item for `key` was found by `get` or the
+    /// `get_or_*` methods since it was inserted, or `None` if the `key`
+    /// isn't in the cache or `with_access_counts` wasn't used.
+    pub fn access_count(&self, key: K) -> Option<u64> {
+        if !self.storage.contains_key(&key) {
+            return None;
+        }
+        let counts = self.access_counts.as_ref()?;
+        Some(counts.get(&key).copied().unwrap_or(0))
+    }
+
+    /// Rebuilds the recency order from the access counts, so that the most
+    /// accessed item becomes the most recently used. Items with equal
+    /// counts keep their current relative order. Does nothing unless
+    /// `with_access_counts` was used.
+    pub fn reorder_by_access_count(&mut self) {
+        let Some(counts) = &self.access_counts else {
+            return;
+        };
+        let mut keys: Vec<K> = self.order.iter().copied().collect();
+        keys.sort_by_key(|key| std::cmp::Reverse(counts.get(key).copied().unwrap_or(0)));
+        self.order = keys.into_iter().collect();
+    }
+
+    /// Returns how often each reuse distance occurred, indexed by
+    /// distance: the number of distinct other keys looked up since the
+    /// last lookup of the same key. First lookups of a key aren't counted.
+    /// Lookups are counted by `get` and the `get_or_*` methods.
+    ///
+    /// A cache of capacity `n` hits exactly the lookups with a distance
+    /// below `n`, so this is the input for a miss-ratio curve. Empty
+    /// unless `with_reuse_tracking` was used.
+    pub fn reuse_histogram(&self) -> Vec<usize> {
+        self.reuse
+            .as_ref()
+            .map(|reuse| reuse.histogram().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Sets how values are turned into bytes for `set_on_evict_serialized`.
+    /// Clones of the cache don't keep it.
+    pub fn set_value_serializer<F: Fn(&V) -> Vec<u8> + Send + 'static>(&mut self, f: F) {
+        self.value_serializer.set(Box::new(f));
     }
 
-    /// Inserts an item into the cache. 
+    /// Sets a callback that receives the key and the serialized value of
+    /// every item evicted to make room, e.g. to write it back to a byte
+    /// store. It only fires once a serializer is set with
+    /// `set_value_serializer`. Clones of the cache don't keep it.
+    pub fn set_on_evict_serialized<F: FnMut(K, Vec<u8>) + Send + 'static>(&mut self, f: F) {
+        self.on_evict_serialized.set(Box::new(f));
+    }
+
+    /// Sets a callback that receives every item still in the cache when
+    /// it is dropped, the least recently used first, e.g. to write back
+    /// dirty values. Clones of the cache don't keep it.
+    pub fn set_on_drop_flush<F: FnMut(K, V) + Send + 'static>(&mut self, f: F) {
+        self.on_drop_flush.set(Box::new(f));
+    }
+
+    /// Stops lookups such as `get` from making items the most recently
+    /// used, e.g. to take a stable pass over the recency order while still
+    /// serving reads. Inserts and evictions work as usual.
+    pub fn set_freeze_recency(&mut self, freeze: bool) {
+        self.freeze_recency = freeze;
+    }
+
+    /// Makes `clear` also release the memory held by the cache.
+    /// Off by default, so clearing keeps the allocation around for reuse.
+    pub fn set_shrink_on_clear(&mut self, shrink: bool) {
+        self.shrink_on_clear = shrink;
+    }
+
+    /// Inserts an item into the cache.
     /// Each item is represented by a key-value pair.
-    /// If the `key` already exists in the cache, 
+    /// If the `key` already exists in the cache,
     /// its corresponding value is updated.
+    ///
+    /// Returns `None` if a new key was turned away by sampled admission;
+    /// see `set_admission_probability`.
     pub fn insert(&mut self, key: K, value: V) -> Option<K> {
-        let mut updated_list = LinkedList::new();
-        // If the list contains this key, then put it as the
-        // front (most recent) element and insert into storage.
-        // If the corresponding is value is new, it'll be updated.;
-        if let Some(index) = self.order.iter().position(|&element| element == key) {
-            updated_list.push_front(key);
-            // Update the list
-            let mut splitted_from_found = self.order.split_off(index);
-            splitted_from_found.pop_front();
-            self.order.append(&mut splitted_from_found);
-            updated_list.append(&mut self.order);
-            self.order = updated_list;
-            // Update storage
-            self.storage.insert(key, value);
-        } else {
-            // It's a new key.
-            //
-            // If length has become equal to the capacity, we need to evict
-            // the "back" (LRU) member, both from the list and storage.
-            if self.order.len() == self.capacity as usize {
-                let evicted = self.order.pop_back();
-                self.storage.remove(&evicted.unwrap());
-            }
-            // Insert the new item
-            self.storage.insert(key, value);
-            self.order.push_front(key);
+        self.ops += 1;
+        self.expire_coldest(self.eager_expire);
+        // A new key has to get past sampled admission first.
+        if !self.order.contains(&key) && !self.admit() {
+            return None;
         }
+        self.put(key, value);
         key.into()
     }
 
+    /// Like `insert`, but when the cache is full the evicted item is the
+    /// least recently used one for which `evictable` returns `true`,
+    /// rather than the least recently used one overall. A full cache grows
+    /// instead if its growth policy allows. Returns the evicted item, if
+    /// any, or gives back the new item without inserting it if nothing is
+    /// evictable. Sampled admission doesn't apply here.
+    ///
+    /// A cache created with `Bound::Weight` or `Bound::Bytes` may have to
+    /// evict several items to fit the new one, so there this always gives
+    /// back the new item without touching the cache.
+    pub fn insert_evicting_matching<F: Fn(&K, &V) -> bool>(
+        &mut self,
+        key: K,
+        value: V,
+        evictable: F,
+    ) -> Result<Option<(K, V)>, (K, V)>
+    where
+        V: Clone,
+    {
+        self.ops += 1;
+        if self.weigher.is_some() {
+            return Err((key, value));
+        }
+        if self.order.contains(&key) || self.fits(1) {
+            self.put(key, value);
+            return Ok(None);
+        }
+        let victim = self.order.iter().rev().copied().find(|victim| {
+            self.storage
+                .get(victim)
+                .is_some_and(|victim_value| evictable(victim, victim_value))
+        });
+        let Some(victim) = victim else {
+            return Err((key, value));
+        };
+        let evicted = self.remove_entry(victim).map(|victim_value| (victim, victim_value));
+        if let Some(evicted) = &evicted {
+            self.note_evictions(std::slice::from_ref(evicted));
+            self.last_evicted = Some(evicted.clone());
+        }
+        self.put(key, value);
+        Ok(evicted)
+    }
+
+    /// Inserts an item so that it ends up at the given recency `rank`,
+    /// where 0 is the most recently used, shifting the items from there
+    /// on one rank back. Ranks past the end are clamped to the end. If the
+    /// `key` is new and the cache is full, it grows if the growth policy
+    /// allows, and the least recently used item is evicted first otherwise.
+    /// Sampled admission doesn't apply here.
+    ///
+    /// Returns the previous value of the `key`, if any.
+    pub fn insert_at_rank(&mut self, key: K, value: V, rank: usize) -> Option<V> {
+        self.ops += 1;
+        self.place_at_rank(key, value, rank)
+    }
+
+    /// Moves the item for `key` to recency rank `rank`, where 0 is the most
+    /// recently used, shifting the items in between by one rank. Ranks past
+    /// the end are clamped to the end.
+    ///
+    /// Returns `false` if the `key` isn't in the cache.
+    pub fn move_to_rank(&mut self, key: K, rank: usize) -> bool {
+        if !self.storage.contains_key(&key) {
+            return false;
+        }
+        self.unlink(key);
+        self.link_at(key, rank);
+        true
+    }
+
+    /// Reserves a slot for `key` at the most recently used end, for an item
+    /// that is expected soon. A later insert of the `key` fills the slot in
+    /// place, without changing the recency order. Until then the slot takes
+    /// up room and ages like any other item, and lookups miss; if it becomes
+    /// the least recently used one, it is evicted like any other item.
+    /// Like an insert, reserving a slot in a full cache grows it if the
+    /// growth policy allows, and evicts the least recently used item
+    /// otherwise.
+    ///
+    /// Does nothing if the `key` is already in the cache or reserved.
+    pub fn prefetch(&mut self, key: K) {
+        if self.storage.contains_key(&key) || self.pending.contains(&key) {
+            return;
+        }
+        self.grow_or_evict();
+        self.order.push_front(key);
+        self.pending.insert(key);
+    }
+
+    /// Like `insert`, but an existing item is only updated if `timestamp`
+    /// is later than when it was inserted (or last updated). The item then
+    /// counts as inserted at `timestamp`, so that later calls compare
+    /// against it. A stale or expired item counts as absent.
+    ///
+    /// Returns whether the item was inserted or updated.
+    pub fn insert_if_newer(&mut self, key: K, value: V, timestamp: Instant) -> bool {
+        self.remove_if_stale(key);
+        if let Some(&current) = self.inserted_at.get(&key) {
+            if timestamp <= current {
+                return false;
+            }
+        }
+        if self.insert(key, value).is_none() {
+            return false;
+        }
+        self.inserted_at.insert(key, timestamp);
+        true
+    }
+
+    /// Like `insert`, but the item expires after `ttl`
+    /// instead of the cache-wide time-to-live.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<K> {
+        let inserted = self.insert(key, value);
+        if self.storage.contains_key(&key) {
+            self.expires_at.insert(key, self.clock.now() + ttl);
+        }
+        inserted
+    }
+
     /// Returns a reference to the value corresponding to the `key`.
     pub fn get(&mut self, key: K) -> Option<&V> {
-        // If the list contains this key, then put it as the
-        // front (most recent) element
-        if self.order.contains(&key) {
-            let mut updated_list = LinkedList::new();
-            updated_list.push_front(key);
-            let found_index = self.order.iter().enumerate().find(|(_, &element)| element == key).map(|found| found.0);
-            
-            // Update the list
-            let mut splitted_from_found = self.order.split_off(found_index.unwrap());
-            splitted_from_found.pop_front();
-            self.order.append(&mut splitted_from_found);
-            updated_list.append(&mut self.order);
-            self.order = updated_list;
+        if self.lookup(key) {
             self.storage.get(&key)
         } else {
             None
         }
     }
-}
 
-#[cfg(test)]
-mod lrutests {
-    use super::LRUCache;
+    /// Looks up all `keys` like `get` does, in order, and returns clones
+    /// of their values at the same positions. Handy for batch reads
+    /// behind a lock, which then only needs to be taken once.
+    pub fn get_many_cloned(&mut self, keys: &[K]) -> Vec<Option<V>>
+    where
+        V: Clone,
+    {
+        keys.iter().map(|&key| self.get(key).cloned()).collect()
+    }
 
-    #[test]
-    fn create_empty_cache() {
-        let cache = LRUCache::<usize, &str>::new(3);
-        assert_eq!(cache.capacity, 3);
-        assert_eq!(cache.order.len(), 0);
-        assert_eq!(cache.storage.len(), 0);
+    /// Like `get`, but also returns the recency rank the item ended up at,
+    /// where 0 is the most recently used. That's always 0, unless the
+    /// recency order is frozen with `set_freeze_recency`.
+    pub fn get_with_rank(&mut self, key: K) -> Option<(&V, usize)> {
+        self.get(key)?;
+        let rank = self.recency_rank(key)?;
+        self.storage.get(&key).map(|value| (value, rank))
     }
 
-    #[test]
-    fn insert_up_to_capacity() {
-        let mut cache = LRUCache::<usize, &str>::new(3);
-        let first = cache.insert(1, "Sadness");
-        let second = cache.insert(2, "Depression");
-        let third = cache.insert(3, "Melancholy");
+    /// Like `get`, but also returns the keys that were next to `key` in the
+    /// recency order before it was promoted: the more recently used one
+    /// first, then the less recently used one, where they exist.
+    pub fn get_with_neighbors(&mut self, key: K) -> Option<(&V, Vec<K>)> {
+        let keys: Vec<K> = self.keys().copied().collect();
+        let neighbors = match keys.iter().position(|&other| other == key) {
+            Some(rank) => {
+                let before = rank.checked_sub(1).map(|rank| keys[rank]);
+                before.into_iter().chain(keys.get(rank + 1).copied()).collect()
+            }
+            None => Vec::new(),
+        };
+        self.get(key).map(|value| (value, neighbors))
+    }
 
-        assert_eq!((first, second, third), (Some(1), Some(2), Some(3)));
+    /// Returns a reference to the value corresponding to the `key`.
+    /// If the `key` isn't in the cache, the value is computed with `f`
+    /// and inserted as the most recently used item. In a weight-bounded
+    /// cache, LRU items are evicted until the new value fits; a value
+    /// heavier than the whole budget evicts everything else.
+    ///
+    /// `f` runs while the cache is mutably borrowed, so it can't call back
+    /// into the same cache: the borrow checker rejects that outright, and a
+    /// cache shared through a `RefCell` is already borrowed when `f` runs,
+    /// so a re-entrant `try_borrow_mut` fails instead of inserting twice.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &V {
+        self.get_or_insert_with_position(key, InsertPos::Front, f)
+    }
 
-        assert_eq!(cache.capacity, 3);
-        assert_eq!(cache.storage.len(), cache.order.len());
+    /// Like `get_or_insert_with`, but on a miss the value is only computed
+    /// and inserted if there's room for it without evicting anything, be it
+    /// a free slot, a slot reserved by `prefetch`, or the growth policy.
+    /// Otherwise `f` isn't called and `None` is returned. In a cache created
+    /// with `Bound::Weight` or `Bound::Bytes` the weight of the value isn't
+    /// known before `f` runs, so this only checks that some of the budget
+    /// is left; a value heavier than the rest still evicts older items.
+    pub fn get_or_insert_with_if_room<F: FnOnce() -> V>(&mut self, key: K, f: F) -> Option<&V> {
+        if !self.lookup(key) {
+            let has_room = (self.pending.contains(&key) || self.fits(1))
+                && (self.weigher.is_none() || self.total_weight < self.max_weight);
+            if !has_room {
+                return None;
+            }
+            self.insert_new(key, f(), InsertPos::Front);
+        }
+        self.storage.get(&key)
     }
 
-    #[test]
-    fn insert_beyond_capacity() {
-        let mut cache = LRUCache::<usize, &str>::new(3);
-        cache.insert(1, "Sadness");
-        cache.insert(2, "Depression");
-        cache.insert(3, "Melancholy");
-        cache.insert(4, "Myth");
+    /// Like `get_or_insert_with`, but an item computed on a miss is
+    /// placed at `pos` in the recency order instead of the front.
+    /// A hit still makes the item the most recently used one.
+    pub fn get_or_insert_with_position<F: FnOnce() -> V>(
+        &mut self,
+        key: K,
+        pos: InsertPos,
+        f: F,
+    ) -> &V {
+        if !self.lookup(key) {
+            self.insert_new(key, f(), pos);
+        }
+        &self.storage[&key]
+    }
 
-        assert_eq!(cache.storage.get(&4), Some(&"Myth"));
+    /// Returns the value for `key`, making it the most recently used item,
+    /// or on a miss inserts `V::default()` at recency rank `rank`, like
+    /// `insert_at_rank`. Defaults placed near the back get evicted before
+    /// the hot items, which keeps one-off lookups from flushing the cache.
+    pub fn get_or_insert_default_at(&mut self, key: K, rank: usize) -> &V
+    where
+        V: Default,
+    {
+        if !self.lookup(key) {
+            self.place_at_rank(key, V::default(), rank);
+        }
+        &self.storage[&key]
+    }
 
-        assert_eq!(cache.order.len(), cache.capacity as usize);
-        assert_eq!(cache.storage.len(), cache.capacity as usize);
+    /// Returns the entry for `key`, for inspecting or updating it in place.
+    /// If the `key` is in the cache, it becomes the most recently used item.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        self.ops += 1;
+        self.remove_if_stale(key);
+        if self.storage.contains_key(&key) {
+            self.promote(key);
+            Entry::Occupied(OccupiedEntry { cache: self, key })
+        } else {
+            Entry::Vacant(VacantEntry { cache: self, key })
+        }
+    }
 
-        // 1 should be removed
-        assert_eq!(cache.order.contains(&1), false);
-        assert_eq!(cache.order.contains(&2), true);
-        assert_eq!(cache.order.contains(&3), true);
-        assert_eq!(cache.order.contains(&4), true);
+    /// Returns a mutable reference to the value of `key`, inserting
+    /// `default` first if the `key` isn't in the cache, along with whether
+    /// that insert happened. Either way the item becomes the most
+    /// recently used one.
+    pub fn get_mut_or_insert_reporting(&mut self, key: K, default: V) -> (&mut V, bool) {
+        let inserted = !self.lookup(key);
+        if inserted {
+            self.insert_new(key, default, InsertPos::Front);
+        }
+        (self.storage.get_mut(&key).unwrap(), inserted)
+    }
 
-        // 4 should be at the front (most recently used)
-        assert_eq!(cache.order.pop_front(), Some(4));
+    /// Returns references to the values of all `keys`, in order, computing
+    /// the missing ones with a single call to `f`. `f` receives each
+    /// missing key once and must return their values in the same order.
+    /// `f` isn't called if nothing is missing.
+    ///
+    /// Inserting the computed values may evict other requested items, e.g.
+    /// if there are more distinct `keys` than fit in the cache at once, or
+    /// the values outweigh the budget; those come back as `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` returns a different number of values than it was
+    /// given keys.
+    pub fn get_or_compute_batch<F: FnOnce(&[K]) -> Vec<V>>(
+        &mut self,
+        keys: &[K],
+        f: F,
+    ) -> Vec<Option<&V>> {
+        let mut missing = Vec::new();
+        for &key in keys {
+            if !missing.contains(&key) && !self.lookup(key) {
+                missing.push(key);
+            }
+        }
+        if !missing.is_empty() {
+            let values = f(&missing);
+            assert_eq!(
+                values.len(),
+                missing.len(),
+                "the batch factory must return one value per missing key"
+            );
+            for (key, value) in missing.into_iter().zip(values) {
+                self.insert_new(key, value, InsertPos::Front);
+            }
+        }
+        keys.iter().map(|key| self.storage.get(key)).collect()
     }
 
-    #[test]
-    fn insert_an_already_existing_item() {
-        let mut cache = LRUCache::<usize, &str>::new(5);
-        cache.insert(1, "Sadness");
-        cache.insert(2, "Depression");
-        cache.insert(3, "Shitty life");
-        assert_eq!(cache.order.len(), 3);
+    /// Returns a reference to the value corresponding to the `key`
+    /// without making it the most recently used item.
+    pub fn peek(&self, key: K) -> Option<&V> {
+        if self.is_stale(key) {
+            return None;
+        }
+        self.storage.get(&key)
+    }
 
-        cache.insert(2, "Melancholy");
+    /// Returns an iterator over the items, from the most to the least
+    /// recently used. Like all iteration, it only needs `&self` and never
+    /// changes the recency order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.order
+            .iter()
+            .filter_map(move |key| self.storage.get_key_value(key))
+    }
 
-        assert_eq!(cache.storage.get(&2), Some(&"Melancholy"));
-        assert_eq!(cache.order.len(), 3);
+    /// Same as `iter`; the name spells out that it is a read-only scan,
+    /// safe to run through a shared borrow such as an `RwLock` read guard.
+    pub fn peek_iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.iter()
+    }
 
-        // Check the list
-        // Expected is:
-        //
-        // 2        3         1
-        // MRU<------------->LRU
-        assert_eq!(cache.order.pop_front(), Some(2));
-        assert_eq!(cache.order.pop_front(), Some(3));
-        assert_eq!(cache.order.pop_front(), Some(1));
+    /// Returns an iterator over the keys, from the most to the least
+    /// recently used.
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.iter().map(|(key, _)| key)
     }
 
-    #[test]
-    fn insert_integers() {
-        let mut cache = LRUCache::<i32, i32>::new(2);
+    /// Returns an iterator over the values, from the most to the least
+    /// recently used.
+    pub fn values(&self) -> impl Iterator<Item = &V> + '_ {
+        self.iter().map(|(_, value)| value)
+    }
 
-        assert_eq!(cache.get(2), None);
+    /// Returns an iterator over every run of `size` consecutive items in
+    /// the recency order, from the most recently used end towards the least
+    /// recently used one. Windows overlap by all but one item; there are
+    /// none if `size` is 0 or more than the number of items.
+    pub fn recency_windows(&self, size: usize) -> impl Iterator<Item = Vec<(&K, &V)>> + '_ {
+        let items: Vec<_> = self.iter().collect();
+        let count = if size == 0 {
+            0
+        } else {
+            (items.len() + 1).saturating_sub(size)
+        };
+        (0..count).map(move |start| items[start..start + size].to_vec())
+    }
 
-        cache.insert(2, 6);
+    /// Returns up to `n` of the most recently used items, the most
+    /// recently used first, without changing the recency order.
+    pub fn hottest(&self, n: usize) -> Vec<(&K, &V)> {
+        self.iter().take(n).collect()
+    }
 
-        assert_eq!(cache.get(1), None);
-        assert_eq!(cache.get(2), Some(&6));
+    /// Returns up to `n` of the least recently used items, the least
+    /// recently used first, without changing the recency order.
+    pub fn coldest(&self, n: usize) -> Vec<(&K, &V)> {
+        self.iter_from_lru().take(n).collect()
+    }
 
-        cache.insert(1, 5);
-        cache.insert(1, 2);
+    /// Returns copies of all items, from the most to the least recently
+    /// used, so the first one is the most recently used.
+    pub fn ordered_pairs(&self) -> Vec<(K, V)>
+    where
+        V: Clone,
+    {
+        self.iter().map(|(key, value)| (*key, value.clone())).collect()
+    }
 
-        assert_eq!(cache.get(1), Some(&2));
-        assert_eq!(cache.get(2), Some(&6));
-        assert_eq!(cache.storage.len(), cache.capacity as usize);
+    /// Returns copies of all items, from the least to the most recently
+    /// used, so the first one is the next to be evicted.
+    pub fn ordered_pairs_rev(&self) -> Vec<(K, V)>
+    where
+        V: Clone,
+    {
+        self.iter_from_lru().map(|(key, value)| (*key, value.clone())).collect()
     }
 
-    #[test]
-    fn get_an_existing_item() {
-        let mut cache = LRUCache::<usize, &str>::new(5);
-        cache.insert(1, "Sadness");
-        cache.insert(2, "Depression");
-        cache.insert(3, "Shitty life");
+    /// Returns how many different values the cache holds, counting equal
+    /// values shared by several keys once.
+    pub fn distinct_value_count(&self) -> usize
+    where
+        V: Eq + Hash,
+    {
+        self.storage.values().collect::<HashSet<_>>().len()
+    }
 
-        let retrieved = cache.get(2);
-        assert_eq!(retrieved, Some(&"Depression"));
+    /// Returns an iterator over mutable references to the values, in no
+    /// particular order, without changing the recency order. If the cache
+    /// is weight-bounded, call `recompute_weights` after changing sizes.
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> + '_ {
+        self.storage.values_mut()
+    }
 
-        // Check the list
-        // Expected is:
-        //
-        // 2        3         1
-        // MRU<------------->LRU
-        assert_eq!(cache.order.pop_front(), Some(2));
-        assert_eq!(cache.order.pop_front(), Some(3));
-        assert_eq!(cache.order.pop_front(), Some(1));
+    /// Returns up to `k` items picked uniformly at random with `rng`,
+    /// without changing the recency order. This still walks over all items
+    /// once (reservoir sampling), but allocates only for the sample.
+    pub fn sample(&self, k: usize, rng: &mut impl Rng) -> Vec<(&K, &V)> {
+        let mut sample = Vec::with_capacity(k.min(self.storage.len()));
+        for (seen, item) in self.iter().enumerate() {
+            if sample.len() < k {
+                sample.push(item);
+            } else {
+                let slot = rng.next_below(seen + 1);
+                if slot < k {
+                    sample[slot] = item;
+                }
+            }
+        }
+        sample
     }
 
-    #[test]
-    fn get_a_nonexisting_item() {
-        let mut cache = LRUCache::<usize, &str>::new(5);
-        cache.insert(1, "Sadness");
-        cache.insert(2, "Depression");
-        cache.insert(3, "Shitty life");
+    /// Returns the position of `key` in the recency order, where 0 is the
+    /// most recently used item, or `None` if the `key` isn't in the cache.
+    /// Slots reserved with `prefetch` hold no item and don't take up a rank.
+    pub fn recency_rank(&self, key: K) -> Option<usize> {
+        self.order
+            .iter()
+            .filter(|element| !self.pending.contains(element))
+            .position(|&element| element == key)
+    }
 
-        let retrieved = cache.get(5);
-        assert_eq!(retrieved, None);
+    /// Returns how many positions apart `a` and `b` are in the recency
+    /// order, or `None` if either isn't in the cache.
+    pub fn recency_distance(&self, a: K, b: K) -> Option<usize> {
+        Some(self.recency_rank(a)?.abs_diff(self.recency_rank(b)?))
+    }
 
-        // check the list
+    /// Returns whether the item for `key` has outlived its time-to-live,
+    /// or `None` if the `key` isn't in the cache. Unlike `get`, this never
+    /// removes the item.
+    pub fn is_expired(&self, key: K) -> Option<bool> {
+        if self.storage.contains_key(&key) {
+            Some(self.expired(key, self.clock.now()))
+        } else {
+            None
+        }
+    }
 
-        // Check the list
-        // Expected is:
-        //
+    /// Returns how many items have outlived their time-to-live,
+    /// without removing any of them.
+    pub fn count_expired(&self) -> usize {
+        let now = self.clock.now();
+        self.expires_at
+            .values()
+            .filter(|&&expires_at| expires_at <= now)
+            .count()
+    }
+
+    /// Removes all expired items and returns how many there were.
+    pub fn evict_expired(&mut self) -> usize {
+        self.drain_expired().len()
+    }
+
+    /// Removes all expired items and returns them, the least recently used
+    /// first. The remaining items keep their recency order.
+    pub fn drain_expired(&mut self) -> Vec<(K, V)> {
+        let now = self.clock.now();
+        let expired: Vec<K> = self
+            .order
+            .iter()
+            .rev()
+            .copied()
+            .filter(|&key| self.expired(key, now))
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| self.remove_entry(key).map(|value| (key, value)))
+            .collect()
+    }
+
+    /// Removes every item inserted before `cutoff`, according to the
+    /// cache's clock, and returns how many there were. Updating an item
+    /// counts as inserting it again.
+    pub fn retain_since(&mut self, cutoff: Instant) -> usize {
+        let old: Vec<K> = self
+            .order
+            .iter()
+            .copied()
+            .filter(|key| self.inserted_at.get(key).is_some_and(|&at| at < cutoff))
+            .collect();
+        for &key in &old {
+            self.remove_entry(key);
+        }
+        old.len()
+    }
+
+    /// Marks the item for `key` as stale, without removing it right away.
+    /// From then on `get` and `peek` treat it as absent, and the next `get`
+    /// removes it. Inserting the `key` again clears the mark.
+    ///
+    /// Returns `false` if the `key` isn't in the cache.
+    pub fn invalidate(&mut self, key: K) -> bool {
+        if self.storage.contains_key(&key) {
+            self.poisoned.insert(key);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Makes every item for which `pred` returns `true` more recently used
+    /// than all other items, keeping the recency order among the promoted
+    /// items themselves. Returns the number of promoted items.
+    pub fn touch_matching<F: Fn(&K, &V) -> bool>(&mut self, pred: F) -> usize {
+        let (mut matching, mut rest): (LinkedList<K>, LinkedList<K>) =
+            self.order.iter().partition(|key| {
+                self.storage
+                    .get(key)
+                    .is_some_and(|value| pred(key, value))
+            });
+        let promoted = matching.len();
+        matching.append(&mut rest);
+        self.order = matching;
+        promoted
+    }
+
+    /// Moves all items of `other` into this cache, in their recency order.
+    /// When both caches hold the same key, the value that was inserted
+    /// (or last updated) later wins; if that is `other`'s, it also becomes
+    /// more recently used here, just as if it had been inserted. Otherwise
+    /// the existing item is left untouched. Stale and expired items count
+    /// as absent on either side.
+    pub fn merge_newest_wins(&mut self, mut other: LRUCache<K, V>) {
+        while let Some(key) = other.order.pop_back() {
+            let stale = other.is_stale(key);
+            let inserted_at = other.inserted_at.get(&key).copied();
+            let (Some(inserted_at), Some(value)) = (inserted_at, other.discard(key)) else {
+                continue;
+            };
+            if stale {
+                continue;
+            }
+            self.remove_if_stale(key);
+            let other_wins = match self.inserted_at.get(&key) {
+                Some(&ours) => inserted_at > ours,
+                None => true,
+            };
+            // Sampled admission may still turn a new key away.
+            if other_wins && self.insert(key, value).is_some() {
+                self.inserted_at.insert(key, inserted_at);
+            }
+        }
+    }
+
+    /// Moves all items of `other` into this cache, in their recency order,
+    /// adding up the values when both caches hold the same key. Every
+    /// merged item becomes more recently used here, as if it had been
+    /// inserted, and LRU items are evicted as needed. Stale and expired
+    /// items count as absent on either side, so they add nothing.
+    pub fn merge_sum(&mut self, mut other: LRUCache<K, V>)
+    where
+        V: Add<Output = V> + Clone,
+    {
+        while let Some(key) = other.order.pop_back() {
+            let stale = other.is_stale(key);
+            let Some(value) = other.discard(key) else {
+                continue;
+            };
+            if stale {
+                continue;
+            }
+            self.remove_if_stale(key);
+            let value = match self.storage.get(&key) {
+                Some(ours) => ours.clone() + value,
+                None => value,
+            };
+            self.insert(key, value);
+        }
+    }
+
+    /// Exchanges the contents of this cache with those of `other`, in
+    /// constant time. The bounds (capacity with its builder range, and
+    /// weight budget) go along with the contents, so each cache stays
+    /// within its bound without evicting anything. Everything else, like
+    /// the clock, time-to-live, callbacks and stats, stays with its cache.
+    pub fn swap_with(&mut self, other: &mut LRUCache<K, V>) {
+        std::mem::swap(&mut self.storage, &mut other.storage);
+        std::mem::swap(&mut self.order, &mut other.order);
+        std::mem::swap(&mut self.capacity, &mut other.capacity);
+        std::mem::swap(&mut self.min_capacity, &mut other.min_capacity);
+        std::mem::swap(&mut self.max_capacity, &mut other.max_capacity);
+        std::mem::swap(&mut self.poisoned, &mut other.poisoned);
+        std::mem::swap(&mut self.weigher, &mut other.weigher);
+        std::mem::swap(&mut self.max_weight, &mut other.max_weight);
+        std::mem::swap(&mut self.total_weight, &mut other.total_weight);
+        std::mem::swap(&mut self.weights, &mut other.weights);
+        std::mem::swap(&mut self.last_evicted, &mut other.last_evicted);
+        std::mem::swap(&mut self.inserted_at, &mut other.inserted_at);
+        std::mem::swap(&mut self.expires_at, &mut other.expires_at);
+        std::mem::swap(&mut self.interned, &mut other.interned);
+        std::mem::swap(&mut self.release_interned, &mut other.release_interned);
+        std::mem::swap(&mut self.access_counts, &mut other.access_counts);
+        std::mem::swap(&mut self.meta, &mut other.meta);
+        std::mem::swap(&mut self.pending, &mut other.pending);
+    }
+
+    /// Removes and returns the least recently used item.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        self.evict_lru()
+    }
+
+    /// Removes and returns the most recently used item.
+    pub fn pop_mru(&mut self) -> Option<(K, V)> {
+        loop {
+            let key = self.order.pop_front()?;
+            if let Some(value) = self.discard(key) {
+                return Some((key, value));
+            }
+        }
+    }
+
+    /// Like `pop_lru`, but returns only the value.
+    pub fn take_lru(&mut self) -> Option<V> {
+        self.pop_lru().map(|(_, value)| value)
+    }
+
+    /// Like `pop_mru`, but returns only the value.
+    pub fn take_mru(&mut self) -> Option<V> {
+        self.pop_mru().map(|(_, value)| value)
+    }
+
+    /// Removes and returns the least recently used item, but only if the
+    /// cache is full, counting slots reserved with `prefetch`. Otherwise
+    /// the cache is left untouched.
+    pub fn pop_lru_if_full(&mut self) -> Option<(K, V)> {
+        if self.is_full() {
+            self.pop_lru()
+        } else {
+            None
+        }
+    }
+
+    /// Runs the periodic housekeeping a timer would otherwise have to do
+    /// piecemeal: removes expired items and items marked stale by
+    /// `invalidate`, which are otherwise only dropped when looked up.
+    pub fn maintenance(&mut self) -> MaintenanceReport {
+        let invalidated: Vec<K> = self.poisoned.iter().copied().collect();
+        for &key in &invalidated {
+            self.remove_entry(key);
+        }
+        MaintenanceReport {
+            expired: self.evict_expired(),
+            invalidated: invalidated.len(),
+        }
+    }
+
+    /// Removes up to `n` of the least recently used items and returns
+    /// them, the least recently used first.
+    pub fn drain_lru(&mut self, n: usize) -> Vec<(K, V)> {
+        let mut drained = Vec::with_capacity(n.min(self.order.len()));
+        while drained.len() < n {
+            match self.evict_lru() {
+                Some(item) => drained.push(item),
+                None => break,
+            }
+        }
+        drained
+    }
+
+    /// Evicts least recently used items until there's room for `n` new
+    /// items, or the cache is empty if `n` is more than the capacity, and
+    /// returns them, the least recently used first. Meant to be called
+    /// before a batch of inserts, which then evict nothing.
+    pub fn make_room_for(&mut self, n: usize) -> Vec<(K, V)> {
+        let free = self.capacity.saturating_sub(self.order.len());
+        let evicted = self.drain_lru(n.min(self.capacity).saturating_sub(free));
+        self.note_evictions(&evicted);
+        evicted
+    }
+
+    /// Evicts the least recently used item along with every other item in
+    /// the same group, as told by `group_of`, and returns them, the least
+    /// recently used first.
+    pub fn evict_coldest_group<G, F>(&mut self, group_of: F) -> Vec<(K, V)>
+    where
+        G: Eq + Hash,
+        F: Fn(&K, &V) -> G,
+    {
+        let Some(group) = self
+            .order
+            .iter()
+            .rev()
+            .find_map(|key| self.storage.get(key).map(|value| group_of(key, value)))
+        else {
+            return Vec::new();
+        };
+        let members: Vec<K> = self
+            .order
+            .iter()
+            .rev()
+            .copied()
+            .filter(|key| {
+                self.storage
+                    .get(key)
+                    .is_some_and(|value| group_of(key, value) == group)
+            })
+            .collect();
+        let evicted: Vec<(K, V)> = members
+            .into_iter()
+            .filter_map(|key| self.remove_entry(key).map(|value| (key, value)))
+            .collect();
+        self.note_evictions(&evicted);
+        evicted
+    }
+
+    /// Weighs every item again and evicts least recently used items until
+    /// the total weight fits the budget, keeping at least one item, like an
+    /// insert would. Returns the evicted items, the least recently used
+    /// first. Does nothing in a cache that isn't weight-bounded.
+    pub fn recompute_weights(&mut self) -> Vec<(K, V)> {
+        let Some(weigher) = self.weigher else {
+            return Vec::new();
+        };
+        self.weights = self
+            .storage
+            .iter()
+            .map(|(key, value)| (*key, weigher(key, value)))
+            .collect();
+        self.total_weight = self.weights.values().sum();
+        let mut evicted = Vec::new();
+        while self.total_weight > self.max_weight && self.storage.len() > 1 {
+            evicted.extend(self.evict_lru());
+        }
+        self.note_evictions(&evicted);
+        evicted
+    }
+
+    /// Evicts the coldest `fraction` of the items, rounded up, and returns
+    /// them, the least recently used first. `fraction` is clamped to
+    /// the range from 0 to 1.
+    pub fn evict_fraction(&mut self, fraction: f64) -> Vec<(K, V)> {
+        let n = (self.storage.len() as f64 * fraction.clamp(0.0, 1.0)).ceil() as usize;
+        let evicted = self.drain_lru(n);
+        self.note_evictions(&evicted);
+        evicted
+    }
+
+    /// Consumes the cache, handing every item to `f`,
+    /// the least recently used first.
+    pub fn consume_into<F: FnMut(K, V)>(mut self, mut f: F) {
+        while let Some((key, value)) = self.evict_lru() {
+            f(key, value);
+        }
+    }
+
+    /// Consumes the cache and returns its items sorted in ascending order
+    /// of the key `f` derives from them. Items with equal sort keys stay in
+    /// recency order, the most recently used first.
+    pub fn into_sorted_by<F, B>(mut self, mut f: F) -> Vec<(K, V)>
+    where
+        F: FnMut(&K, &V) -> B,
+        B: Ord,
+    {
+        let mut items = Vec::with_capacity(self.storage.len());
+        while let Some(key) = self.order.pop_front() {
+            if let Some(value) = self.discard(key) {
+                items.push((key, value));
+            }
+        }
+        items.sort_by_key(|(key, value)| f(key, value));
+        items
+    }
+
+    /// Returns every item with its recency rank, where 0 is the most
+    /// recently used. `import_state` rebuilds the cache from the result.
+    /// Stale and expired items are left out.
+    pub fn export_state(&self) -> Vec<(K, V, usize)>
+    where
+        V: Clone,
+    {
+        self.iter()
+            .filter(|(key, _)| !self.is_stale(**key))
+            .map(|(key, value)| (*key, value.clone()))
+            .enumerate()
+            .map(|(rank, (key, value))| (key, value, rank))
+            .collect()
+    }
+
+    /// Returns copies of the items whose recency ranks fall in `range`,
+    /// where 0 is the most recently used, in recency order. The `range` is
+    /// clamped to the items in the cache. Like in `export_state`, stale and
+    /// expired items are left out and don't take up a rank. Doesn't change
+    /// the recency order.
+    pub fn export_ranks(&self, range: Range<usize>) -> Vec<(K, V)>
+    where
+        V: Clone,
+    {
+        self.order
+            .iter()
+            .filter(|key| !self.is_stale(**key))
+            .filter_map(|key| self.storage.get(key).map(|value| (*key, value.clone())))
+            .skip(range.start)
+            .take(range.end.saturating_sub(range.start))
+            .collect()
+    }
+
+    /// Replaces the contents of the cache with `state`, ordering the items
+    /// by their recency rank, where 0 is the most recently used. If there
+    /// are more items than the capacity, the lowest ranks survive. Sampled
+    /// admission doesn't turn away any of these.
+    pub fn import_state(&mut self, mut state: Vec<(K, V, usize)>) {
+        self.clear();
+        state.sort_by_key(|&(_, _, rank)| std::cmp::Reverse(rank));
+        for (key, value, _) in state {
+            self.put(key, value);
+        }
+    }
+
+    /// Creates a new cache by transforming every item with `f`, keeping
+    /// the recency order and the capacity. Weight bounds aren't carried
+    /// over, since the weigher works on the old types. Stale and expired
+    /// items are left out.
+    ///
+    /// If two keys map to the same new key, the more recently used item
+    /// wins and the other one is dropped.
+    pub fn map<K2, V2, F>(&self, mut f: F) -> LRUCache<K2, V2>
+    where
+        K2: Eq + PartialEq + Copy + Hash,
+        F: FnMut(&K, &V) -> (K2, V2),
+    {
+        let mut mapped = LRUCache::new(self.capacity);
+        for (key, value) in self.live_from_lru() {
+            let (key, value) = f(key, value);
+            mapped.insert(key, value);
+        }
+        mapped
+    }
+
+    /// Creates a new cache of the given `capacity` holding copies of the
+    /// items for which `pred` returns `true`, in the same recency order.
+    /// If more items match than fit, the most recently used ones are kept.
+    /// Stale and expired items are left out.
+    pub fn clone_filtered<F>(&self, capacity: usize, pred: F) -> LRUCache<K, V>
+    where
+        V: Clone,
+        F: Fn(&K, &V) -> bool,
+    {
+        let mut filtered = LRUCache::new(capacity);
+        for (key, value) in self.live_from_lru() {
+            if pred(key, value) {
+                filtered.insert(*key, value.clone());
+            }
+        }
+        filtered
+    }
+
+    /// Consumes the cache and splits it into the items for which `pred`
+    /// returns `true` and the rest. Both caches keep the recency order of
+    /// their items and get a capacity equal to their length. Stale and
+    /// expired items are dropped without being passed to `pred`.
+    pub fn partition<F>(mut self, pred: F) -> (LRUCache<K, V>, LRUCache<K, V>)
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.maintenance();
+        let (mut matching, mut rest) = (Vec::new(), Vec::new());
+        while let Some((key, value)) = self.evict_lru() {
+            if pred(&key, &value) {
+                matching.push((key, value));
+            } else {
+                rest.push((key, value));
+            }
+        }
+        (
+            LRUCache::from_iter_with_capacity(matching.len(), matching),
+            LRUCache::from_iter_with_capacity(rest.len(), rest),
+        )
+    }
+
+    /// Returns the item that was most recently evicted to make room,
+    /// either by an insert or by shrinking the cache. It is kept until
+    /// the next eviction replaces it, or until the cache is cleared.
+    /// Items evicted in bulk by methods like `make_room_for`, which hand
+    /// them back, don't show up here.
+    pub fn last_evicted(&self) -> Option<&(K, V)> {
+        self.last_evicted.as_ref()
+    }
+
+    /// Replaces the contents of the cache with `entries`, inserted in
+    /// order, so the last one is the most recently used and only the last
+    /// ones survive if there are more than the capacity. Returns the old
+    /// contents, the least recently used first.
+    ///
+    /// Unlike `insert`, sampled admission doesn't turn away any of these.
+    pub fn replace_all<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) -> Vec<(K, V)> {
+        let old = self.drain_lru(self.order.len());
+        for (key, value) in entries {
+            self.put(key, value);
+        }
+        old
+    }
+
+    /// Inserts all of `items` in order if they fit next to the current
+    /// contents without evicting anything, or none of them otherwise, in
+    /// which case they are handed back. Updates to keys already in the
+    /// cache don't need room, and neither does capacity that the growth
+    /// policy would add. Only the item count is checked, not weights.
+    ///
+    /// Unlike `insert`, sampled admission doesn't turn away any of these.
+    pub fn try_insert_all(&mut self, items: Vec<(K, V)>) -> Result<(), Vec<(K, V)>> {
+        let new_keys: HashSet<K> = items
+            .iter()
+            .map(|&(key, _)| key)
+            .filter(|key| !self.order.contains(key))
+            .collect();
+        if !self.fits(new_keys.len()) {
+            return Err(items);
+        }
+        for (key, value) in items {
+            self.put(key, value);
+        }
+        Ok(())
+    }
+
+    /// Inserts the items of `iter`, combining the values of a key that
+    /// shows up more than once with `reduce(earlier, later)` first. Items
+    /// are inserted in the order their keys first show up, and a combined
+    /// value replaces whatever the cache held for that key before.
+    pub fn load_reducing<I, F>(&mut self, iter: I, mut reduce: F)
+    where
+        I: IntoIterator<Item = (K, V)>,
+        F: FnMut(&V, V) -> V,
+    {
+        let mut batch: Vec<(K, V)> = Vec::new();
+        let mut index: HashMap<K, usize> = HashMap::new();
+        for (key, value) in iter {
+            match index.get(&key) {
+                Some(&i) => {
+                    let combined = reduce(&batch[i].1, value);
+                    batch[i].1 = combined;
+                }
+                None => {
+                    index.insert(key, batch.len());
+                    batch.push((key, value));
+                }
+            }
+        }
+        for (key, value) in batch {
+            self.insert(key, value);
+        }
+    }
+
+    /// Removes all items from the cache.
+    /// If `shrink_on_clear` is set, the backing storage is shrunk as well.
+    pub fn clear(&mut self) {
+        self.storage.clear();
+        self.order.clear();
+        self.poisoned.clear();
+        self.weights.clear();
+        self.total_weight = 0;
+        self.last_evicted = None;
+        self.inserted_at.clear();
+        self.expires_at.clear();
+        self.interned.clear();
+        if let Some(counts) = &mut self.access_counts {
+            counts.clear();
+        }
+        self.meta.0.clear();
+        self.pending.clear();
+        if self.shrink_on_clear {
+            self.storage.shrink_to_fit();
+        }
+    }
+
+    /// Shrinks the memory held by the cache to fit its current contents,
+    /// leaving a quarter of headroom so that the next few inserts don't
+    /// immediately have to grow it again. Meant to be called now and then
+    /// after many items were removed.
+    pub fn rightsize(&mut self) {
+        let len = self.storage.len();
+        self.storage.shrink_to(len + len / 4);
+    }
+
+    // Like `iter`, but from the least to the most recently used.
+    fn iter_from_lru(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.order
+            .iter()
+            .rev()
+            .filter_map(move |key| self.storage.get_key_value(key))
+    }
+
+    // Like `iter_from_lru`, but skips stale and expired items.
+    fn live_from_lru(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.iter_from_lru().filter(move |(key, _)| !self.is_stale(**key))
+    }
+
+    // Moves `key` to the front (most recent) of the list.
+    fn move_to_front(&mut self, key: K) {
+        if self.unlink(key) {
+            self.order.push_front(key);
+        }
+    }
+
+    // Takes `key` out of the list, returning whether it was there.
+    fn unlink(&mut self, key: K) -> bool {
+        match self.order.iter().position(|&element| element == key) {
+            Some(index) => {
+                let mut splitted_from_found = self.order.split_off(index);
+                splitted_from_found.pop_front();
+                self.order.append(&mut splitted_from_found);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Removes the back (LRU) item, both from the list and storage.
+    // Placeholders on the way are dropped, since they hold no item.
+    fn evict_lru(&mut self) -> Option<(K, V)> {
+        loop {
+            let key = self.order.pop_back()?;
+            if let Some(value) = self.discard(key) {
+                return Some((key, value));
+            }
+        }
+    }
+
+    // Evicts the LRU item to make room, remembering it for `last_evicted`.
+    // A placeholder at the back makes room by itself.
+    fn evict_for_room(&mut self) {
+        if let Some(&key) = self.order.back() {
+            if self.pending.remove(&key) {
+                self.order.pop_back();
+                return;
+            }
+        }
+        if let Some(evicted) = self.evict_lru() {
+            self.note_evictions(std::slice::from_ref(&evicted));
+            self.last_evicted = Some(evicted);
+        }
+    }
+
+    // Counts items that were evicted and hands them to the serialized
+    // eviction callback, if there is one along with a serializer.
+    fn note_evictions(&mut self, evicted: &[(K, V)]) {
+        self.stats.evictions += evicted.len() as u64;
+        if let (Some(serialize), Some(on_evict)) =
+            (self.value_serializer.get_mut(), self.on_evict_serialized.get_mut())
+        {
+            for (key, value) in evicted {
+                on_evict(*key, serialize(value));
+            }
+        }
+    }
+
+    // Removes `key` from the list, storage and all bookkeeping.
+    fn remove_entry(&mut self, key: K) -> Option<V> {
+        self.unlink(key);
+        self.discard(key)
+    }
+
+    // Removes `key` from storage and all bookkeeping, but not from the list.
+    fn discard(&mut self, key: K) -> Option<V> {
+        self.poisoned.remove(&key);
+        self.pending.remove(&key);
+        if let Some(counts) = &mut self.access_counts {
+            counts.remove(&key);
+        }
+        self.meta.0.remove(&key);
+        self.inserted_at.remove(&key);
+        self.expires_at.remove(&key);
+        if let Some(weight) = self.weights.remove(&key) {
+            self.total_weight -= weight;
+        }
+        let value = self.storage.remove(&key);
+        if let Some(value) = &value {
+            self.release(value);
+        }
+        value
+    }
+
+    // Tells the pool of `insert_interned` that an item left the cache.
+    fn release(&mut self, value: &V) {
+        if let Some(release_interned) = self.release_interned {
+            release_interned(&mut self.interned, value);
+        }
+    }
+
+    // Puts the value into storage, keeping the bookkeeping up to date.
+    fn store(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(weigher) = self.weigher {
+            let weight = weigher(&key, &value);
+            self.total_weight += weight;
+            if let Some(old) = self.weights.insert(key, weight) {
+                self.total_weight -= old;
+            }
+        }
+        self.poisoned.remove(&key);
+        self.pending.remove(&key);
+        let now = self.clock.now();
+        self.inserted_at.insert(key, now);
+        match self.ttl {
+            Some(ttl) => self.expires_at.insert(key, now + ttl),
+            None => self.expires_at.remove(&key),
+        };
+        let old = self.storage.insert(key, value);
+        if let Some(old) = &old {
+            self.release(old);
+        }
+        old
+    }
+
+    // Evicts LRU items until the total weight fits the budget again,
+    // sparing `keep`, the item that was just stored, even if it has been
+    // placed at the back. An item heavier than the whole budget is kept
+    // on its own.
+    fn evict_overweight(&mut self, keep: K) {
+        while self.total_weight > self.max_weight && self.order.len() > 1 {
+            if self.order.back() != Some(&keep) {
+                self.evict_for_room();
+                continue;
+            }
+            let victim = self.order.iter().rev().nth(1).copied();
+            if let Some(evicted) = victim.and_then(|victim| {
+                self.remove_entry(victim).map(|value| (victim, value))
+            }) {
+                self.note_evictions(std::slice::from_ref(&evicted));
+                self.last_evicted = Some(evicted);
+            }
+        }
+    }
+
+    fn expired(&self, key: K, now: Instant) -> bool {
+        self.expires_at
+            .get(&key)
+            .is_some_and(|&expires_at| expires_at <= now)
+    }
+
+    // Removes the expired items among the `n` least recently used.
+    fn expire_coldest(&mut self, n: usize) {
+        if n == 0 || self.expires_at.is_empty() {
+            return;
+        }
+        let now = self.clock.now();
+        let expired: Vec<K> = self
+            .order
+            .iter()
+            .rev()
+            .take(n)
+            .copied()
+            .filter(|&key| self.expired(key, now))
+            .collect();
+        for key in expired {
+            self.remove_entry(key);
+        }
+    }
+
+    // Whether the item was marked by `invalidate` or has expired.
+    fn is_stale(&self, key: K) -> bool {
+        self.poisoned.contains(&key) || self.expired(key, self.clock.now())
+    }
+
+    // Lazily drops an item that `get` should no longer see.
+    fn remove_if_stale(&mut self, key: K) {
+        if self.is_stale(key) {
+            self.remove_entry(key);
+        }
+    }
+
+    // The bookkeeping shared by all lookups. Returns whether the `key`
+    // was found, in which case it's now the most recently used.
+    fn lookup(&mut self, key: K) -> bool {
+        self.ops += 1;
+        self.record_lookup(key);
+        self.remove_if_stale(key);
+        // If the cache holds this key, then put it as the
+        // front (most recent) element
+        if self.storage.contains_key(&key) {
+            self.stats.hits += 1;
+            self.record_segment(key, true);
+            if let Some(counts) = &mut self.access_counts {
+                *counts.entry(key).or_insert(0) += 1;
+            }
+            self.promote(key);
+            true
+        } else {
+            self.missed(key);
+            false
+        }
+    }
+
+    // Makes a looked up item the most recently used,
+    // unless the recency order is frozen.
+    fn promote(&mut self, key: K) {
+        if !self.freeze_recency {
+            self.move_to_front(key);
+        }
+    }
+
+    fn record_lookup(&mut self, key: K) {
+        if let Some(reuse) = &mut self.reuse {
+            reuse.record(key);
+        }
+    }
+
+    fn missed(&mut self, key: K) {
+        self.stats.misses += 1;
+        self.record_segment(key, false);
+        if let Some(on_miss) = self.on_miss.get_mut() {
+            on_miss(&key);
+        }
+    }
+
+    fn record_segment(&mut self, key: K, hit: bool) {
+        self.segments.record(&key, hit);
+    }
+
+    // Whether `n` more slots fit without evicting anything, counting the
+    // capacity that the growth policy would add on the way.
+    fn fits(&self, n: usize) -> bool {
+        let needed = self.order.len().saturating_add(n);
+        let mut capacity = self.capacity;
+        while capacity < needed {
+            match self.growth_policy.grow(capacity, self.max_capacity) {
+                Some(grown) => capacity = grown,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    // Decides whether sampled admission lets a new key in.
+    fn admit(&mut self) -> bool {
+        self.admission_probability >= 1.0 || self.rng.next_f64() < self.admission_probability
+    }
+
+    // Inserts or updates an item, bypassing sampled admission.
+    fn put(&mut self, key: K, value: V) {
+        // If the cache holds this key, then put it as the
+        // front (most recent) element and insert into storage.
+        // If the corresponding is value is new, it'll be updated.
+        if self.storage.contains_key(&key) {
+            self.move_to_front(key);
+            self.store(key, value);
+            self.evict_overweight(key);
+        } else {
+            // It's a new key.
+            self.insert_new(key, value, InsertPos::Front);
+        }
+    }
+
+    // Inserts a key that isn't in the cache yet at `pos`.
+    fn insert_new(&mut self, key: K, value: V, pos: InsertPos) {
+        // A prefetched key already has its slot.
+        if self.pending.contains(&key) {
+            self.store(key, value);
+            self.evict_overweight(key);
+            return;
+        }
+        self.grow_or_evict();
+        self.store(key, value);
+        match pos {
+            InsertPos::Front => self.order.push_front(key),
+            InsertPos::Middle => self.link_at(key, self.len() / 2),
+            InsertPos::Back => self.order.push_back(key),
+        }
+        self.evict_overweight(key);
+    }
+
+    // Makes room for a new key in a full cache. If the cache isn't allowed
+    // to grow, we need to evict the "back" (LRU) member, both from the list
+    // and storage.
+    fn grow_or_evict(&mut self) {
+        if self.is_full() {
+            match self.growth_policy.grow(self.capacity, self.max_capacity) {
+                Some(grown) => self.capacity = grown,
+                None => self.evict_for_room(),
+            }
+        }
+    }
+
+    // Stores the item and puts it at recency rank `rank`, making room for
+    // a new key first. Returns the previous value of the `key`, if any.
+    fn place_at_rank(&mut self, key: K, value: V, rank: usize) -> Option<V> {
+        if !self.unlink(key) {
+            self.grow_or_evict();
+        }
+        let old = self.store(key, value);
+        self.link_at(key, rank);
+        self.evict_overweight(key);
+        old
+    }
+
+    // Puts `key` into the list so that it ends up at recency rank `rank`,
+    // or at the back if there are fewer items than that. Like in
+    // `recency_rank`, prefetch placeholders don't take up a rank.
+    fn link_at(&mut self, key: K, rank: usize) {
+        let index = self
+            .order
+            .iter()
+            .enumerate()
+            .filter(|(_, element)| !self.pending.contains(element))
+            .nth(rank)
+            .map_or(self.order.len(), |(index, _)| index);
+        let mut rest = self.order.split_off(index);
+        self.order.push_back(key);
+        self.order.append(&mut rest);
+    }
+}
+
+impl<K, V> Drop for LRUCache<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    fn drop(&mut self) {
+        let mut on_drop_flush = std::mem::take(&mut self.on_drop_flush);
+        if let Some(flush) = on_drop_flush.get_mut() {
+            while let Some((key, value)) = self.evict_lru() {
+                flush(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod lrutests {
+    use super::{
+        Bound, BuildError, Clock, Entry, FrozenCache, GrowthPolicy, InsertPos, LRUCache,
+        MaintenanceReport, MockClock, SLRUCache, ShardedCache, SplitMix64, StatsDelta,
+    };
+    use std::time::Duration;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex, RwLock};
+
+    #[test]
+    fn create_empty_cache() {
+        let cache = LRUCache::<usize, &str>::new(3);
+        assert_eq!(cache.capacity, 3);
+        assert_eq!(cache.order.len(), 0);
+        assert_eq!(cache.storage.len(), 0);
+    }
+
+    #[test]
+    fn insert_up_to_capacity() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        let first = cache.insert(1, "Sadness");
+        let second = cache.insert(2, "Depression");
+        let third = cache.insert(3, "Melancholy");
+
+        assert_eq!((first, second, third), (Some(1), Some(2), Some(3)));
+
+        assert_eq!(cache.capacity, 3);
+        assert_eq!(cache.storage.len(), cache.order.len());
+    }
+
+    #[test]
+    fn insert_beyond_capacity() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+        cache.insert(4, "Myth");
+
+        assert_eq!(cache.storage.get(&4), Some(&"Myth"));
+
+        assert_eq!(cache.order.len(), cache.capacity);
+        assert_eq!(cache.storage.len(), cache.capacity);
+
+        // 1 should be removed
+        assert!(!cache.order.contains(&1));
+        assert!(cache.order.contains(&2));
+        assert!(cache.order.contains(&3));
+        assert!(cache.order.contains(&4));
+
+        // 4 should be at the front (most recently used)
+        assert_eq!(cache.order.pop_front(), Some(4));
+    }
+
+    #[test]
+    fn insert_an_already_existing_item() {
+        let mut cache = LRUCache::<usize, &str>::new(5);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Shitty life");
+        assert_eq!(cache.order.len(), 3);
+
+        cache.insert(2, "Melancholy");
+
+        assert_eq!(cache.storage.get(&2), Some(&"Melancholy"));
+        assert_eq!(cache.order.len(), 3);
+
+        // Check the list
+        // Expected is:
+        //
+        // 2        3         1
+        // MRU<------------->LRU
+        assert_eq!(cache.order.pop_front(), Some(2));
+        assert_eq!(cache.order.pop_front(), Some(3));
+        assert_eq!(cache.order.pop_front(), Some(1));
+    }
+
+    #[test]
+    fn insert_integers() {
+        let mut cache = LRUCache::<i32, i32>::new(2);
+
+        assert_eq!(cache.get(2), None);
+
+        cache.insert(2, 6);
+
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get(2), Some(&6));
+
+        cache.insert(1, 5);
+        cache.insert(1, 2);
+
+        assert_eq!(cache.get(1), Some(&2));
+        assert_eq!(cache.get(2), Some(&6));
+        assert_eq!(cache.storage.len(), cache.capacity);
+    }
+
+    #[test]
+    fn get_an_existing_item() {
+        let mut cache = LRUCache::<usize, &str>::new(5);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Shitty life");
+
+        let retrieved = cache.get(2);
+        assert_eq!(retrieved, Some(&"Depression"));
+
+        // Check the list
+        // Expected is:
+        //
+        // 2        3         1
+        // MRU<------------->LRU
+        assert_eq!(cache.order.pop_front(), Some(2));
+        assert_eq!(cache.order.pop_front(), Some(3));
+        assert_eq!(cache.order.pop_front(), Some(1));
+    }
+
+    #[test]
+    fn get_a_nonexisting_item() {
+        let mut cache = LRUCache::<usize, &str>::new(5);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Shitty life");
+
+        let retrieved = cache.get(5);
+        assert_eq!(retrieved, None);
+
+        // check the list
+
+        // Check the list
+        // Expected is:
+        //
         // 3        2         1
         // MRU<------------->LRU
         assert_eq!(cache.order.pop_front(), Some(3));
         assert_eq!(cache.order.pop_front(), Some(2));
-        assert_eq!(cache.order.pop_front(), Some(1));
+        assert_eq!(cache.order.pop_front(), Some(1));
+    }
+
+    #[test]
+    fn clear_keeps_allocation_by_default() {
+        let mut cache = LRUCache::<usize, usize>::new(100);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        let allocated = cache.storage.capacity();
+        cache.clear();
+
+        assert_eq!(cache.storage.len(), 0);
+        assert_eq!(cache.order.len(), 0);
+        assert_eq!(cache.storage.capacity(), allocated);
+    }
+
+    #[test]
+    fn clear_with_shrink_on_clear() {
+        let mut cache = LRUCache::<usize, usize>::new(100);
+        cache.set_shrink_on_clear(true);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        let allocated = cache.storage.capacity();
+        cache.clear();
+
+        assert_eq!(cache.storage.len(), 0);
+        assert_eq!(cache.order.len(), 0);
+        assert!(cache.storage.capacity() < allocated);
+    }
+
+    #[test]
+    fn get_or_insert_with_hit_and_miss() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+
+        assert_eq!(cache.get_or_insert_with(1, || "Melancholy"), &"Sadness");
+        assert_eq!(cache.get_or_insert_with(3, || "Melancholy"), &"Melancholy");
+
+        assert_eq!(cache.order.pop_front(), Some(3));
+        assert_eq!(cache.order.pop_front(), Some(1));
+        assert_eq!(cache.order.pop_front(), Some(2));
+    }
+
+    #[test]
+    fn get_or_insert_with_position_at_back() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+
+        // 1 is evicted to make room, and 4 becomes the coldest item.
+        let inserted = cache.get_or_insert_with_position(4, InsertPos::Back, || "Myth");
+        assert_eq!(inserted, &"Myth");
+        assert!(!cache.storage.contains_key(&1));
+
+        // Expected is:
+        //
+        // 3        2         4
+        // MRU<------------->LRU
+        assert_eq!(cache.order.pop_front(), Some(3));
+        assert_eq!(cache.order.pop_front(), Some(2));
+        assert_eq!(cache.order.pop_front(), Some(4));
+    }
+
+    #[test]
+    fn from_iter_with_capacity_keeps_last_items() {
+        let items = vec![(1, "Sadness"), (2, "Depression"), (3, "Melancholy"), (4, "Myth")];
+        let mut cache = LRUCache::from_iter_with_capacity(3, items);
+
+        assert_eq!(cache.capacity, 3);
+        assert_eq!(cache.storage.len(), 3);
+        assert!(!cache.storage.contains_key(&1));
+
+        // Expected is:
+        //
+        // 4        3         2
+        // MRU<------------->LRU
+        assert_eq!(cache.order.pop_front(), Some(4));
+        assert_eq!(cache.order.pop_front(), Some(3));
+        assert_eq!(cache.order.pop_front(), Some(2));
+    }
+
+    #[test]
+    fn invalidate_an_item() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+
+        assert!(cache.invalidate(1));
+        assert!(!cache.invalidate(5));
+
+        // Still stored, but no longer visible.
+        assert!(cache.storage.contains_key(&1));
+        assert_eq!(cache.peek(1), None);
+
+        // `get` removes it for good.
+        assert_eq!(cache.get(1), None);
+        assert!(!cache.storage.contains_key(&1));
+        assert!(!cache.order.contains(&1));
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get(2), Some(&"Depression"));
+    }
+
+    #[test]
+    fn insert_clears_invalidation() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.invalidate(1);
+        cache.insert(1, "Melancholy");
+
+        assert_eq!(cache.peek(1), Some(&"Melancholy"));
+        assert_eq!(cache.get(1), Some(&"Melancholy"));
+    }
+
+    #[test]
+    fn touch_matching_promotes_in_order() {
+        let mut cache = LRUCache::<usize, usize>::new(5);
+        for i in 1..=5 {
+            cache.insert(i, i * 10);
+        }
+        // 5 4 3 2 1
+        let promoted = cache.touch_matching(|key, _| key % 2 == 0);
+        assert_eq!(promoted, 2);
+
+        // Expected is:
+        //
+        // 4        2        5        3         1
+        // MRU<------------------------------->LRU
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![4, 2, 5, 3, 1]);
+    }
+
+    #[test]
+    fn resize_evicts_when_shrinking() {
+        let mut cache = LRUCache::<usize, usize>::new(5);
+        for i in 1..=5 {
+            cache.insert(i, i);
+        }
+        assert!(cache.resize(3));
+        assert_eq!(cache.capacity(), 3);
+
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![5, 4, 3]);
+        assert_eq!(cache.storage.len(), 3);
+    }
+
+    #[test]
+    fn resize_respects_cooldown() {
+        let mut cache = LRUCache::<usize, usize>::new(4);
+        cache.set_resize_cooldown(10);
+
+        assert!(cache.resize(8));
+        let mut changes = 0;
+        for i in 0..9 {
+            cache.insert(i, i);
+            if cache.resize(if i % 2 == 0 { 4 } else { 8 }) {
+                changes += 1;
+            }
+        }
+        // Only 9 operations since the first resize.
+        assert_eq!(changes, 0);
+        assert_eq!(cache.capacity(), 8);
+
+        cache.get(0);
+        assert!(cache.resize(4));
+        assert_eq!(cache.capacity(), 4);
+    }
+
+    #[test]
+    fn get_or_insert_with_cannot_reenter() {
+        let cache = Rc::new(RefCell::new(LRUCache::<usize, &str>::new(3)));
+        let shared = Rc::clone(&cache);
+
+        let mut reentered = false;
+        let value = *cache.borrow_mut().get_or_insert_with(1, || {
+            // The factory tries to fill the same key itself.
+            if let Ok(mut inner) = shared.try_borrow_mut() {
+                inner.insert(1, "Depression");
+                reentered = true;
+            }
+            "Sadness"
+        });
+
+        assert!(!reentered);
+        assert_eq!(value, "Sadness");
+        assert_eq!(cache.borrow().storage.len(), 1);
+        assert_eq!(cache.borrow().order.len(), 1);
+    }
+
+    #[test]
+    fn bounded_by_count() {
+        let mut cache = LRUCache::<usize, &str>::bounded_by(Bound::Count(2));
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+
+        assert_eq!(cache.capacity(), 2);
+        assert_eq!(cache.total_weight(), 0);
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![3, 2]);
+    }
+
+    #[test]
+    fn bounded_by_weight() {
+        let mut cache =
+            LRUCache::<usize, &str>::bounded_by(Bound::Weight(10, |_, value| value.len()));
+        cache.insert(1, "Myth");
+        cache.insert(2, "Sad");
+        assert_eq!(cache.total_weight(), 7);
+
+        // 4 + 3 + 7 doesn't fit, so 1 goes.
+        cache.insert(3, "Sadness");
+        assert_eq!(cache.total_weight(), 10);
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![3, 2]);
+
+        // 10 + 4 doesn't fit, and evicting 2 alone isn't enough.
+        cache.insert(4, "Myth");
+        assert_eq!(cache.total_weight(), 4);
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![4]);
+    }
+
+    #[test]
+    fn bounded_by_weight_oversized_item_is_kept_alone() {
+        let mut cache =
+            LRUCache::<usize, &str>::bounded_by(Bound::Weight(5, |_, value| value.len()));
+        cache.insert(1, "Sad");
+        cache.insert(2, "Melancholy");
+
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![2]);
+        assert_eq!(cache.total_weight(), 10);
+    }
+
+    #[test]
+    fn bounded_by_bytes() {
+        // Each item takes 16 bytes.
+        let mut cache = LRUCache::<u64, u64>::bounded_by(Bound::Bytes(40));
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.insert(3, 3);
+
+        assert_eq!(cache.total_weight(), 32);
+        let order: Vec<u64> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![3, 2]);
+    }
+
+    #[test]
+    fn last_evicted_reports_the_lru_item() {
+        let mut cache = LRUCache::<usize, &str>::new(2);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        assert_eq!(cache.last_evicted(), None);
+
+        cache.insert(3, "Melancholy");
+        assert_eq!(cache.last_evicted(), Some(&(1, "Sadness")));
+
+        // Non-evicting operations keep it around.
+        cache.get(2);
+        cache.insert(2, "Myth");
+        assert_eq!(cache.last_evicted(), Some(&(1, "Sadness")));
+
+        cache.insert(4, "Shitty life");
+        assert_eq!(cache.last_evicted(), Some(&(3, "Melancholy")));
+    }
+
+    #[test]
+    fn merge_newest_wins_prefers_later_insert() {
+        let clock = MockClock::new();
+        let mut first = LRUCache::<usize, &str>::new(5);
+        first.set_clock(clock.clone());
+        let mut second = LRUCache::<usize, &str>::new(5);
+        second.set_clock(clock.clone());
+
+        second.insert(1, "Melancholy");
+        clock.advance(Duration::from_secs(1));
+        first.insert(1, "Sadness");
+        first.insert(2, "Depression");
+        clock.advance(Duration::from_secs(1));
+        second.insert(2, "Myth");
+        second.insert(3, "Shitty life");
+
+        first.merge_newest_wins(second);
+
+        assert_eq!(first.peek(1), Some(&"Sadness"));
+        assert_eq!(first.peek(2), Some(&"Myth"));
+        assert_eq!(first.peek(3), Some(&"Shitty life"));
+        let order: Vec<usize> = first.order.iter().copied().collect();
+        assert_eq!(order, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn drain_lru_removes_coldest() {
+        let mut cache = LRUCache::<usize, usize>::new(5);
+        for i in 1..=5 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.drain_lru(2), vec![(1, 10), (2, 20)]);
+
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![5, 4, 3]);
+        assert_eq!(cache.storage.len(), 3);
+
+        assert_eq!(cache.drain_lru(10).len(), 3);
+        assert_eq!(cache.storage.len(), 0);
+    }
+
+    #[test]
+    fn map_transforms_keys_and_values() {
+        let mut cache = LRUCache::<usize, &str>::new(5);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+
+        let mut mapped = cache.map(|&key, value| (key * 10, value.len()));
+        assert_eq!(mapped.capacity(), 5);
+        assert_eq!(mapped.peek(10), Some(&7));
+        assert_eq!(mapped.peek(20), Some(&10));
+        assert_eq!(mapped.peek(30), Some(&10));
+        assert_eq!(mapped.order.pop_front(), Some(30));
+        assert_eq!(mapped.order.pop_front(), Some(20));
+        assert_eq!(mapped.order.pop_front(), Some(10));
+    }
+
+    #[test]
+    fn map_collision_keeps_more_recent() {
+        let mut cache = LRUCache::<usize, &str>::new(5);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+        cache.get(1);
+
+        // 1 and 3 both map to 1, and 1 is the more recently used.
+        let mapped = cache.map(|&key, &value| (key % 2, value));
+        assert_eq!(mapped.storage.len(), 2);
+        assert_eq!(mapped.peek(1), Some(&"Sadness"));
+        let order: Vec<usize> = mapped.order.iter().copied().collect();
+        assert_eq!(order, vec![1, 0]);
+    }
+
+    #[test]
+    fn build_with_initial_entries() {
+        let mut cache = LRUCache::builder()
+            .capacity(3)
+            .entries(vec![(1, "Sadness"), (2, "Depression"), (3, "Melancholy"), (4, "Myth")])
+            .build()
+            .unwrap();
+
+        assert_eq!(cache.capacity(), 3);
+        assert!(!cache.storage.contains_key(&1));
+        assert_eq!(cache.order.pop_front(), Some(4));
+        assert_eq!(cache.order.pop_front(), Some(3));
+        assert_eq!(cache.order.pop_front(), Some(2));
+    }
+
+    #[test]
+    fn build_without_capacity() {
+        let built = LRUCache::<usize, &str>::builder().build();
+        assert_eq!(built.unwrap_err(), BuildError::MissingCapacity);
+    }
+
+    #[test]
+    fn consume_into_goes_lru_first() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+        cache.get(1);
+
+        let mut consumed = Vec::new();
+        cache.consume_into(|key, value| consumed.push((key, value)));
+        assert_eq!(consumed, vec![(2, "Depression"), (3, "Melancholy"), (1, "Sadness")]);
+    }
+
+    #[test]
+    fn expired_items_are_absent() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.set_clock(clock.clone());
+        cache.set_ttl(Some(Duration::from_secs(10)));
+        cache.insert(1, "Sadness");
+        cache.insert_with_ttl(2, "Depression", Duration::from_secs(30));
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(cache.peek(1), None);
+        assert_eq!(cache.get(1), None);
+        assert!(!cache.storage.contains_key(&1));
+        assert_eq!(cache.get(2), Some(&"Depression"));
+    }
+
+    #[test]
+    fn is_expired_does_not_remove() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.set_clock(clock.clone());
+        cache.insert_with_ttl(1, "Sadness", Duration::from_secs(5));
+        cache.insert(2, "Depression");
+
+        assert_eq!(cache.is_expired(1), Some(false));
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(cache.is_expired(1), Some(true));
+        assert_eq!(cache.is_expired(2), Some(false));
+        assert_eq!(cache.is_expired(3), None);
+
+        // Still there.
+        assert!(cache.storage.contains_key(&1));
+        assert!(cache.order.contains(&1));
+
+        assert_eq!(cache.evict_expired(), 1);
+        assert_eq!(cache.is_expired(1), None);
+    }
+
+    #[test]
+    fn count_expired_with_staggered_ttls() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::<usize, usize>::new(5);
+        cache.set_clock(clock.clone());
+        for i in 1..=4 {
+            cache.insert_with_ttl(i, i, Duration::from_secs(i as u64 * 10));
+        }
+        cache.insert(5, 5);
+        assert_eq!(cache.count_expired(), 0);
+
+        clock.advance(Duration::from_secs(25));
+        assert_eq!(cache.count_expired(), 2);
+        clock.advance(Duration::from_secs(100));
+        assert_eq!(cache.count_expired(), 4);
+
+        // Nothing was removed.
+        assert_eq!(cache.storage.len(), 5);
+        assert_eq!(cache.order.len(), 5);
+    }
+
+    #[test]
+    fn into_sorted_by_value() {
+        let mut cache = LRUCache::<&str, usize>::new(4);
+        cache.insert("Sadness", 3);
+        cache.insert("Depression", 1);
+        cache.insert("Melancholy", 4);
+        cache.insert("Myth", 2);
+
+        let sorted = cache.into_sorted_by(|_, &value| value);
+        assert_eq!(
+            sorted,
+            vec![("Depression", 1), ("Myth", 2), ("Sadness", 3), ("Melancholy", 4)]
+        );
+    }
+
+    #[test]
+    fn sampled_admission_is_deterministic_with_seed() {
+        let admitted = |seed| {
+            let mut cache = LRUCache::<usize, usize>::new(100);
+            cache.set_rng_seed(seed);
+            cache.set_admission_probability(0.5);
+            (0..20)
+                .filter(|&i| cache.insert(i, i).is_some())
+                .collect::<Vec<_>>()
+        };
+        let first = admitted(42);
+        assert_eq!(first, admitted(42));
+        assert_eq!(first, vec![1, 2, 3, 4, 6, 8, 10, 11, 15, 16, 17, 18]);
+    }
+
+    #[test]
+    fn sampled_admission_always_applies_updates() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.set_admission_probability(0.0);
+
+        assert_eq!(cache.insert(2, "Depression"), None);
+        assert_eq!(cache.insert(1, "Melancholy"), Some(1));
+        assert_eq!(cache.peek(1), Some(&"Melancholy"));
+        assert_eq!(cache.peek(2), None);
+    }
+
+    #[test]
+    fn export_and_import_state_round_trip() {
+        let mut cache = LRUCache::<usize, &str>::new(4);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+        cache.get(1);
+
+        let state = cache.export_state();
+        assert_eq!(
+            state,
+            vec![(1, "Sadness", 0), (3, "Melancholy", 1), (2, "Depression", 2)]
+        );
+
+        let mut imported = LRUCache::<usize, &str>::new(4);
+        let mut shuffled = state.clone();
+        shuffled.reverse();
+        imported.import_state(shuffled);
+        assert_eq!(imported.export_state(), state);
+        assert_eq!(imported.order, cache.order);
+        assert_eq!(imported.storage, cache.storage);
+    }
+
+    #[test]
+    fn is_full_drives_eviction() {
+        let mut cache = LRUCache::<usize, &str>::new(2);
+        assert!(!cache.is_full());
+        cache.insert(1, "Sadness");
+        assert!(!cache.is_full());
+        cache.insert(2, "Depression");
+        assert!(cache.is_full());
+
+        // Updating doesn't evict, a new key does.
+        cache.insert(2, "Melancholy");
+        assert_eq!(cache.storage.len(), 2);
+        cache.insert(3, "Myth");
+        assert!(cache.is_full());
+        assert_eq!(cache.storage.len(), cache.capacity());
+        assert_eq!(cache.last_evicted(), Some(&(1, "Sadness")));
+    }
+
+    #[test]
+    fn get_or_compute_batch_computes_only_misses() {
+        let mut cache = LRUCache::<usize, usize>::new(5);
+        cache.insert(1, 10);
+        cache.insert(3, 30);
+
+        let mut requested = Vec::new();
+        let values = cache.get_or_compute_batch(&[1, 2, 3, 4, 2], |missing| {
+            requested.extend_from_slice(missing);
+            missing.iter().map(|key| key * 100).collect()
+        });
+        assert_eq!(values, vec![Some(&10), Some(&200), Some(&30), Some(&400), Some(&200)]);
+        assert_eq!(requested, vec![2, 4]);
+
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![4, 2, 3, 1]);
+    }
+
+    #[test]
+    fn recency_rank_and_distance() {
+        let mut cache = LRUCache::<usize, usize>::new(5);
+        for i in 1..=5 {
+            cache.insert(i, i);
+        }
+        // 5 4 3 2 1
+        assert_eq!(cache.recency_rank(5), Some(0));
+        assert_eq!(cache.recency_rank(2), Some(3));
+        assert_eq!(cache.recency_rank(6), None);
+
+        assert_eq!(cache.recency_distance(4, 1), Some(3));
+        assert_eq!(cache.recency_distance(1, 4), Some(3));
+        assert_eq!(cache.recency_distance(3, 3), Some(0));
+        assert_eq!(cache.recency_distance(3, 6), None);
+    }
+
+    #[test]
+    fn resize_clamps_to_min_and_max() {
+        let mut cache = LRUCache::<usize, usize>::builder()
+            .capacity(4)
+            .min_capacity(2)
+            .max_capacity(8)
+            .build()
+            .unwrap();
+
+        assert!(cache.resize(100));
+        assert_eq!(cache.capacity(), 8);
+        assert!(cache.resize(0));
+        assert_eq!(cache.capacity(), 2);
+        assert!(!cache.resize(1));
+    }
+
+    #[test]
+    fn build_rejects_inconsistent_range() {
+        let built = LRUCache::<usize, usize>::builder()
+            .capacity(10)
+            .min_capacity(2)
+            .max_capacity(8)
+            .build();
+        assert_eq!(
+            built.unwrap_err(),
+            BuildError::CapacityOutOfRange { min: 2, capacity: 10, max: 8 }
+        );
+
+        let built = LRUCache::<usize, usize>::builder()
+            .capacity(4)
+            .min_capacity(8)
+            .max_capacity(2)
+            .build();
+        assert!(built.is_err());
+    }
+
+    #[test]
+    fn pop_lru_if_full_only_when_full() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        assert_eq!(cache.pop_lru_if_full(), None);
+        assert_eq!(cache.storage.len(), 2);
+
+        cache.insert(3, "Melancholy");
+        assert_eq!(cache.pop_lru_if_full(), Some((1, "Sadness")));
+        assert_eq!(cache.storage.len(), 2);
+        assert!(!cache.order.contains(&1));
+        assert_eq!(cache.pop_lru_if_full(), None);
+    }
+
+    #[test]
+    fn entry_occupied_and_vacant() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+
+        match cache.entry(1) {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.key(), &1);
+                assert_eq!(entry.insert("Melancholy"), "Sadness");
+            }
+            Entry::Vacant(_) => panic!("1 should be occupied"),
+        }
+        assert_eq!(cache.order.front(), Some(&1));
+        assert_eq!(cache.peek(1), Some(&"Melancholy"));
+
+        *cache.entry(3).or_insert("Myth") = "Shitty life";
+        assert_eq!(cache.peek(3), Some(&"Shitty life"));
+    }
+
+    #[test]
+    fn vacant_entry_into_key_inserts_nothing() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+
+        match cache.entry(7) {
+            Entry::Vacant(entry) => {
+                assert_eq!(entry.key(), &7);
+                assert_eq!(entry.into_key(), 7);
+            }
+            Entry::Occupied(_) => panic!("7 should be vacant"),
+        }
+        assert_eq!(cache.storage.len(), 1);
+        assert!(!cache.order.contains(&7));
+    }
+
+    #[test]
+    fn maintenance_removes_expired_and_invalidated() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::<usize, usize>::new(5);
+        cache.set_clock(clock.clone());
+        cache.insert_with_ttl(1, 1, Duration::from_secs(5));
+        cache.insert_with_ttl(2, 2, Duration::from_secs(5));
+        cache.insert(3, 3);
+        cache.insert(4, 4);
+        cache.invalidate(3);
+
+        clock.advance(Duration::from_secs(5));
+        let report = cache.maintenance();
+        assert_eq!(report, MaintenanceReport { expired: 2, invalidated: 1 });
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![4]);
+
+        assert_eq!(cache.maintenance(), MaintenanceReport::default());
+    }
+
+    #[test]
+    fn get_with_rank_reports_promotion() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+
+        assert_eq!(cache.get_with_rank(1), Some((&"Sadness", 0)));
+        assert_eq!(cache.get_with_rank(4), None);
+        assert_eq!(cache.recency_rank(3), Some(1));
+    }
+
+    #[test]
+    fn insert_interned_shares_values() {
+        let mut cache = LRUCache::<usize, Rc<String>>::new(10);
+        for i in 0..20 {
+            cache.insert_interned(i, String::from("Sadness"));
+        }
+        cache.insert_interned(20, String::from("Depression"));
+
+        assert_eq!(cache.interned.len(), 2);
+        let first = Rc::clone(&cache.storage[&11]);
+        assert!(cache
+            .storage
+            .values()
+            .filter(|value| value.as_str() == "Sadness")
+            .all(|value| Rc::ptr_eq(value, &first)));
+        // 9 items in the cache, plus `last_evicted`, the pool and `first`.
+        assert_eq!(Rc::strong_count(&first), 12);
+
+        let old = cache.insert_interned(20, String::from("Sadness"));
+        assert_eq!(old.as_deref().map(String::as_str), Some("Depression"));
+        assert_eq!(cache.interned.len(), 1);
+
+        // Once the last item is gone, the pooled value is released.
+        cache.drain_lru(10);
+        assert!(cache.interned.is_empty());
+    }
+
+    #[test]
+    fn on_miss_fires_only_on_misses() {
+        let missed = Arc::new(Mutex::new(Vec::new()));
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        let recorder = Arc::clone(&missed);
+        cache.set_on_miss(move |&key| recorder.lock().unwrap().push(key));
+
+        cache.insert(1, "Sadness");
+        cache.get(1);
+        cache.get(2);
+        cache.get_or_insert_with(1, || "Depression");
+        cache.get_or_insert_with(3, || "Melancholy");
+        cache.get(3);
+        cache.get(4);
+
+        assert_eq!(*missed.lock().unwrap(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn replace_all_swaps_contents() {
+        let mut cache = LRUCache::<usize, &str>::new(2);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+
+        let old = cache.replace_all(vec![(3, "Melancholy"), (4, "Myth"), (5, "Shitty life")]);
+        assert_eq!(old, vec![(1, "Sadness"), (2, "Depression")]);
+
+        assert_eq!(cache.capacity(), 2);
+        assert_eq!(cache.storage.len(), 2);
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![5, 4]);
+    }
+
+    #[test]
+    fn iterate_through_a_read_guard() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+        cache.get(1);
+        let lock = RwLock::new(cache);
+
+        let guard = lock.read().unwrap();
+        let items: Vec<(&usize, &&str)> = guard.peek_iter().collect();
+        assert_eq!(items, vec![(&1, &"Sadness"), (&3, &"Melancholy"), (&2, &"Depression")]);
+        assert!(guard.iter().eq(guard.peek_iter()));
+        assert_eq!(guard.keys().copied().collect::<Vec<_>>(), vec![1, 3, 2]);
+        assert_eq!(
+            guard.values().copied().collect::<Vec<_>>(),
+            vec!["Sadness", "Melancholy", "Depression"]
+        );
+    }
+
+    #[test]
+    fn insert_evicting_matching_skips_unevictable() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "dirty");
+        cache.insert(2, "clean");
+        cache.insert(3, "dirty");
+
+        let clean = |_: &usize, value: &&str| *value == "clean";
+        assert_eq!(cache.insert_evicting_matching(4, "dirty", clean), Ok(Some((2, "clean"))));
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+        assert_eq!(order, vec![4, 3, 1]);
+    }
+
+    #[test]
+    fn insert_evicting_matching_with_nothing_evictable() {
+        let mut cache = LRUCache::<usize, &str>::new(2);
+        cache.insert(1, "dirty");
+        cache.insert(2, "dirty");
+
+        let clean = |_: &usize, value: &&str| *value == "clean";
+        assert_eq!(cache.insert_evicting_matching(3, "clean", clean), Err((3, "clean")));
+        assert!(!cache.storage.contains_key(&3));
+        assert_eq!(cache.storage.len(), 2);
+
+        // Updating an existing key needs no room.
+        assert_eq!(cache.insert_evicting_matching(1, "clean", clean), Ok(None));
+        assert_eq!(cache.peek(1), Some(&"clean"));
+    }
+
+    #[test]
+    fn stats_count_hits_misses_and_evictions() {
+        let mut cache = LRUCache::<usize, &str>::new(2);
+        cache.insert(1, "Sadness");
+        cache.get(1);
+        cache.get(2);
+        cache.get_or_insert_with(2, || "Depression");
+        cache.get_or_insert_with(3, || "Melancholy");
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 3, 1));
+        assert_eq!(stats.hit_ratio(), 0.25);
+    }
+
+    #[test]
+    fn recommend_capacity_grows_when_thrashing() {
+        let mut cache = LRUCache::<usize, usize>::new(4);
+        // Cycling through one key more than fits evicts each key
+        // right before it is needed again.
+        for _ in 0..10 {
+            for key in 0..5 {
+                cache.get_or_insert_with(key, || key);
+            }
+        }
+        assert_eq!(cache.stats().hits, 0);
+        assert!(cache.recommend_capacity() > cache.capacity());
+
+        let mut cache = LRUCache::<usize, usize>::new(4);
+        for _ in 0..10 {
+            for key in 0..4 {
+                cache.get_or_insert_with(key, || key);
+            }
+        }
+        assert_eq!(cache.recommend_capacity(), cache.capacity());
+    }
+
+    #[test]
+    fn reuse_histogram_buckets_distances() {
+        let mut cache = LRUCache::<char, usize>::new(2).with_reuse_tracking();
+        for key in ['a', 'b', 'c', 'a', 'b', 'b', 'c'] {
+            cache.get_or_insert_with(key, || 0);
+        }
+        // a: 2 (b, c), b: 2 (c, a), b: 0, c: 2 (a, b)
+        assert_eq!(cache.reuse_histogram(), vec![1, 0, 3]);
+
+        // Tracking is off by default.
+        let mut cache = LRUCache::<char, usize>::new(2);
+        cache.get('a');
+        cache.get('a');
+        assert!(cache.reuse_histogram().is_empty());
+    }
+
+    #[test]
+    fn rightsize_shrinks_and_keeps_items() {
+        let mut cache = LRUCache::<usize, usize>::new(1000);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        cache.drain_lru(990);
+        let before = cache.storage.capacity();
+        let order: Vec<usize> = cache.order.iter().copied().collect();
+
+        cache.rightsize();
+        assert!(cache.storage.capacity() < before);
+        assert!(cache.storage.capacity() >= 12);
+        assert_eq!(cache.order.iter().copied().collect::<Vec<_>>(), order);
+        assert!((990..1000).all(|i| cache.peek(i) == Some(&i)));
+    }
+
+    #[test]
+    fn clone_filtered_keeps_hottest_matches() {
+        let mut cache = LRUCache::<usize, usize>::new(6);
+        for i in 1..=6 {
+            cache.insert(i, i * 10);
+        }
+        cache.get(2);
+        // 2 6 5 4 3 1
+
+        let mut filtered = cache.clone_filtered(2, |key, _| key % 2 == 0);
+        assert_eq!(filtered.capacity(), 2);
+        assert_eq!(filtered.order.pop_front(), Some(2));
+        assert_eq!(filtered.order.pop_front(), Some(6));
+        assert_eq!(filtered.order.pop_front(), None);
+
+        let filtered = cache.clone_filtered(5, |_, &value| value > 30);
+        assert_eq!(filtered.keys().copied().collect::<Vec<_>>(), vec![6, 5, 4]);
+        assert_eq!(filtered.peek(4), Some(&40));
+        assert_eq!(cache.storage.len(), 6);
+    }
+
+    #[test]
+    fn get_mut_or_insert_reporting_tells_inserts_apart() {
+        let mut cache = LRUCache::<usize, usize>::new(3);
+
+        let (value, inserted) = cache.get_mut_or_insert_reporting(1, 10);
+        assert!(inserted);
+        *value += 1;
+
+        let (value, inserted) = cache.get_mut_or_insert_reporting(1, 10);
+        assert!(!inserted);
+        assert_eq!(*value, 11);
+    }
+
+    #[test]
+    fn partition_by_value_parity() {
+        let mut cache = LRUCache::<usize, usize>::new(6);
+        for i in 1..=5 {
+            cache.insert(i, i * 3);
+        }
+        cache.get(2);
+        // 2 5 4 3 1
+
+        let (odd, even) = cache.partition(|_, value| value % 2 == 1);
+        assert_eq!(odd.capacity(), 3);
+        assert_eq!(odd.iter().collect::<Vec<_>>(), vec![(&5, &15), (&3, &9), (&1, &3)]);
+        assert_eq!(even.capacity(), 2);
+        assert_eq!(even.iter().collect::<Vec<_>>(), vec![(&2, &6), (&4, &12)]);
+    }
+
+    #[test]
+    fn stats_snapshot_deltas() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::<usize, usize>::new(2);
+        cache.set_clock(clock.clone());
+        cache.get_or_insert_with(1, || 1);
+
+        let earlier = cache.stats_snapshot();
+        cache.get(1);
+        cache.get(1);
+        cache.get(2);
+        cache.insert(2, 2);
+        cache.insert(3, 3);
+        clock.advance(Duration::from_secs(3));
+        let later = cache.stats_snapshot();
+
+        assert_eq!(
+            later.since(&earlier),
+            StatsDelta { hits: 2, misses: 1, evictions: 1, elapsed: Duration::from_secs(3) }
+        );
+        assert_eq!(later.stats, cache.stats());
+    }
+
+    #[test]
+    fn insert_at_rank_places_items() {
+        let mut cache = LRUCache::<usize, &str>::new(4);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        // 2 1
+
+        assert_eq!(cache.insert_at_rank(3, "Melancholy", 1), None);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 3, 1]);
+        assert_eq!(cache.insert_at_rank(4, "Myth", 10), None);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 3, 1, 4]);
+        assert_eq!(cache.insert_at_rank(2, "Shitty life", 2), Some("Depression"));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![3, 1, 2, 4]);
+
+        // Full, so 4 is evicted before 5 goes in.
+        cache.insert_at_rank(5, "Sadness", 0);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![5, 3, 1, 2]);
+    }
+
+    #[test]
+    fn drop_flushes_remaining_items() {
+        let flushed = Arc::new(Mutex::new(Vec::new()));
+        let mut cache = LRUCache::<usize, &str>::new(2);
+        let recorder = Arc::clone(&flushed);
+        cache.set_on_drop_flush(move |key, value| recorder.lock().unwrap().push((key, value)));
+
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+        cache.get(2);
+        // A clone doesn't flush.
+        drop(cache.clone());
+        assert!(flushed.lock().unwrap().is_empty());
+
+        drop(cache);
+        assert_eq!(*flushed.lock().unwrap(), vec![(3, "Melancholy"), (2, "Depression")]);
+    }
+
+    #[test]
+    fn sample_is_deterministic_with_seeded_rng() {
+        let mut cache = LRUCache::<usize, usize>::new(100);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        let order: Vec<usize> = cache.keys().copied().collect();
+
+        let sample = cache.sample(5, &mut SplitMix64::new(7));
+        assert_eq!(sample.len(), 5);
+        assert_eq!(sample, cache.sample(5, &mut SplitMix64::new(7)));
+        assert!(sample.iter().all(|(key, value)| key == value));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), order);
+
+        assert_eq!(cache.sample(500, &mut SplitMix64::new(7)).len(), 100);
+    }
+
+    #[test]
+    fn eager_expire_reclaims_a_bounded_number() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::<usize, usize>::new(20);
+        cache.set_clock(clock.clone());
+        cache.set_eager_expire(3);
+        for i in 0..10 {
+            cache.insert_with_ttl(i, i, Duration::from_secs(1));
+        }
+        clock.advance(Duration::from_secs(1));
+
+        cache.insert(100, 100);
+        assert_eq!(cache.storage.len(), 8);
+        cache.insert(101, 101);
+        assert_eq!(cache.storage.len(), 6);
+        for i in 102..110 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.count_expired(), 0);
+        assert_eq!(cache.storage.len(), 10);
+        assert!((100..110).all(|i| cache.peek(i).is_some()));
+    }
+
+    #[test]
+    fn get_or_insert_with_honors_weight_budget() {
+        let mut cache =
+            LRUCache::<usize, &str>::bounded_by(Bound::Weight(10, |_, value| value.len()));
+        cache.insert(1, "Sad");
+        cache.insert(2, "Sad");
+        cache.insert(3, "Sad");
+
+        // 9 + 7 doesn't fit, so the two coldest go.
+        assert_eq!(cache.get_or_insert_with(4, || "Sadness"), &"Sadness");
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![4, 3]);
+        assert_eq!(cache.total_weight(), 10);
+        assert_eq!(cache.stats().evictions, 2);
+
+        // Even at the back, the new value is what's kept.
+        let value = cache.get_or_insert_with_position(5, InsertPos::Back, || "My");
+        assert_eq!(value, &"My");
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![4, 5]);
+    }
+
+    #[test]
+    fn get_or_insert_with_oversized_value_is_kept_alone() {
+        let mut cache =
+            LRUCache::<usize, &str>::bounded_by(Bound::Weight(5, |_, value| value.len()));
+        cache.insert(1, "Sad");
+
+        assert_eq!(cache.get_or_insert_with(2, || "Melancholy"), &"Melancholy");
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(cache.total_weight(), 10);
+    }
+
+    #[test]
+    fn frozen_recency_ignores_gets() {
+        let mut cache = LRUCache::<usize, &str>::new(3);
+        cache.insert(1, "Sadness");
+        cache.insert(2, "Depression");
+        cache.insert(3, "Melancholy");
+        cache.set_freeze_recency(true);
+
+        assert_eq!(cache.get(1), Some(&"Sadness"));
+        assert_eq!(cache.get_with_rank(2), Some((&"Depression", 1)));
+        assert_eq!(cache.get_or_insert_with(1, || "Myth"), &"Sadness");
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![3, 2, 1]);
+
+        // Inserts still go to the front and evict.
+        cache.insert(4, "Myth");
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![4, 3, 2]);
+
+        cache.set_freeze_recency(false);
+        cache.get(2);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 4, 3]);
+    }
+
+    #[test]
+    fn get_many_cloned_aligns_results() {
+        let mut cache = LRUCache::<usize, String>::new(4);
+        for i in 1..=4 {
+            cache.insert(i, i.to_string());
+        }
+        // 4 3 2 1
+
+        let values = cache.get_many_cloned(&[1, 7, 2]);
+        assert_eq!(values, vec![Some(String::from("1")), None, Some(String::from("2"))]);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 1, 4, 3]);
+    }
+
+    #[test]
+    fn growth_policies_progress_capacity() {
+        let progression = |policy| {
+            let mut cache = LRUCache::new(2).with_growth_policy(policy);
+            let mut capacities = Vec::new();
+            for i in 0..12 {
+                cache.insert(i, i);
+                capacities.push(cache.capacity());
+            }
+            (capacities, cache.keys().count())
+        };
+        assert_eq!(progression(GrowthPolicy::Fixed), (vec![2; 12], 2));
+        assert_eq!(
+            progression(GrowthPolicy::Double { max: 8 }),
+            (vec![2, 2, 4, 4, 8, 8, 8, 8, 8, 8, 8, 8], 8)
+        );
+        assert_eq!(
+            progression(GrowthPolicy::Linear { step: 3, max: 10 }),
+            (vec![2, 2, 5, 5, 5, 8, 8, 8, 10, 10, 10, 10], 10)
+        );
+    }
+
+    #[test]
+    fn growth_respects_builder_max_capacity() {
+        let mut cache = LRUCache::builder()
+            .capacity(2)
+            .max_capacity(3)
+            .build()
+            .unwrap()
+            .with_growth_policy(GrowthPolicy::Double { max: 100 });
+        for i in 0..5 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.capacity(), 3);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![4, 3, 2]);
+    }
+
+    #[test]
+    fn diff_since_categorizes_changes() {
+        let mut cache = LRUCache::new(4);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        cache.insert(3, "c");
+        let cp = cache.checkpoint();
+
+        cache.insert(2, "B");
+        cache.insert(3, "c");
+        cache.invalidate(1);
+        cache.insert(4, "d");
+        cache.get(2);
+
+        let diff = cache.diff_since(&cp);
+        assert_eq!(diff.added, [4].into_iter().collect());
+        assert_eq!(diff.removed, [1].into_iter().collect());
+        assert_eq!(diff.modified, [2].into_iter().collect());
+
+        cache.rollback(&cp);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![3, 2, 1]);
+        assert!(cache.diff_since(&cp).added.is_empty());
+        assert_eq!(cache.peek(2), Some(&"b"));
+    }
+
+    #[test]
+    fn retain_since_drops_older_entries() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::new(5);
+        cache.set_clock(clock.clone());
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        clock.advance(Duration::from_secs(10));
+        let cutoff = clock.now();
+        cache.insert(3, 3);
+        cache.insert(4, 4);
+        cache.insert(1, 10);
+
+        assert_eq!(cache.retain_since(cutoff), 1);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![1, 4, 3]);
+        assert_eq!(cache.retain_since(cutoff), 0);
+    }
+
+    #[test]
+    fn with_seed_is_reproducible() {
+        let run = || {
+            let mut cache = LRUCache::with_seed(8, 7);
+            cache.set_admission_probability(0.5);
+            for i in 0..40 {
+                cache.insert(i, i * 2);
+                cache.get(i / 2);
+            }
+            let slots: Vec<_> = cache.storage.iter().map(|(&k, &v)| (k, v)).collect();
+            (slots, cache.keys().copied().collect::<Vec<_>>())
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn export_ranks_returns_window() {
+        let mut cache = LRUCache::new(6);
+        for i in 0..6 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.export_ranks(2..4), vec![(3, 30), (2, 20)]);
+        assert_eq!(cache.export_ranks(4..100), vec![(1, 10), (0, 0)]);
+        assert!(cache.export_ranks(7..9).is_empty());
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![5, 4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn merge_sum_adds_overlapping_counters() {
+        let mut ours = LRUCache::new(4);
+        ours.insert("a", 1);
+        ours.insert("b", 2);
+        ours.insert("c", 3);
+        let mut theirs = LRUCache::new(4);
+        theirs.insert("b", 10);
+        theirs.insert("d", 4);
+        theirs.insert("c", 20);
+
+        ours.merge_sum(theirs);
+        assert_eq!(ours.keys().copied().collect::<Vec<_>>(), vec!["c", "d", "b", "a"]);
+        assert_eq!(ours.peek("a"), Some(&1));
+        assert_eq!(ours.peek("b"), Some(&12));
+        assert_eq!(ours.peek("c"), Some(&23));
+        assert_eq!(ours.peek("d"), Some(&4));
+    }
+
+    #[test]
+    fn load_reducing_combines_duplicates() {
+        let mut cache = LRUCache::new(4);
+        cache.insert("b", 100);
+        let stream = vec![("a", 1), ("b", 2), ("a", 3), ("c", 4), ("a", 5)];
+        cache.load_reducing(stream, |earlier, later| earlier + later);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec!["c", "b", "a"]);
+        assert_eq!(cache.peek("a"), Some(&9));
+        assert_eq!(cache.peek("b"), Some(&2));
+        assert_eq!(cache.peek("c"), Some(&4));
+    }
+
+    #[test]
+    fn make_room_for_evicts_coldest() {
+        let mut cache = LRUCache::new(5);
+        for i in 0..5 {
+            cache.insert(i, i);
+        }
+        cache.get(0);
+        assert_eq!(cache.make_room_for(3), vec![(1, 1), (2, 2), (3, 3)]);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![0, 4]);
+        assert_eq!(cache.stats().evictions, 3);
+        assert!(cache.make_room_for(2).is_empty());
+        assert_eq!(cache.make_room_for(9).len(), 2);
+    }
+
+    #[test]
+    fn hottest_returns_mru_entries() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        cache.get(1);
+        assert_eq!(cache.hottest(2), vec![(&1, &10), (&3, &30)]);
+        assert_eq!(cache.hottest(10).len(), 4);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn coldest_returns_lru_entries() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        cache.get(0);
+        assert_eq!(cache.coldest(2), vec![(&1, &10), (&2, &20)]);
+        assert_eq!(cache.coldest(10).len(), 4);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![0, 3, 2, 1]);
+    }
+
+    #[test]
+    fn try_insert_all_commits_when_it_fits() {
+        let mut cache = LRUCache::new(4);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.try_insert_all(vec![(2, "B"), (3, "c"), (4, "d"), (3, "C")]), Ok(()));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![3, 4, 2, 1]);
+        assert_eq!(cache.peek(2), Some(&"B"));
+        assert_eq!(cache.peek(3), Some(&"C"));
+    }
+
+    #[test]
+    fn try_insert_all_rejects_whole_batch() {
+        let mut cache = LRUCache::new(3);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        let batch = vec![(1, "A"), (3, "c"), (4, "d")];
+        assert_eq!(cache.try_insert_all(batch.clone()), Err(batch));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(cache.peek(1), Some(&"a"));
+    }
+
+    #[test]
+    fn reorder_by_access_count_ranks_by_hits() {
+        let mut cache = LRUCache::new(5).with_access_counts();
+        for i in 0..5 {
+            cache.insert(i, i);
+        }
+        for (key, hits) in [(0, 3), (1, 1), (3, 3), (4, 2)] {
+            for _ in 0..hits {
+                cache.get(key);
+            }
+        }
+        assert_eq!(cache.access_count(3), Some(3));
+        assert_eq!(cache.access_count(2), Some(0));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![4, 3, 1, 0, 2]);
+
+        cache.reorder_by_access_count();
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![3, 0, 4, 1, 2]);
+        assert_eq!(LRUCache::<u8, u8>::new(1).access_count(0), None);
+    }
+
+    #[test]
+    fn swap_with_exchanges_contents() {
+        let mut front = LRUCache::new(3);
+        front.insert(1, "a");
+        front.insert(2, "b");
+        let mut back = LRUCache::new(5);
+        for (key, value) in [(7, "x"), (8, "y"), (9, "z"), (10, "w")] {
+            back.insert(key, value);
+        }
+
+        front.swap_with(&mut back);
+        assert_eq!(front.keys().copied().collect::<Vec<_>>(), vec![10, 9, 8, 7]);
+        assert_eq!(back.keys().copied().collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(front.peek(9), Some(&"z"));
+        assert_eq!(back.peek(1), Some(&"a"));
+        assert_eq!((front.capacity(), back.capacity()), (5, 3));
+    }
+
+    #[test]
+    fn iter_with_meta_pairs_entries() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        assert!(cache.set_meta(1, "one"));
+        assert!(cache.set_meta(3, "three"));
+        assert!(cache.set_meta(2, 2.5f64));
+        assert!(!cache.set_meta(9, "nine"));
+        cache.insert(1, 11);
+
+        let items: Vec<_> = cache.iter_with_meta::<&str>().collect();
+        assert_eq!(
+            items,
+            vec![(&1, &11, Some(&"one")), (&3, &30, Some(&"three")), (&2, &20, None), (&0, &0, None)]
+        );
+        cache.pop_lru();
+        cache.pop_lru();
+        cache.insert(3, 33);
+        cache.insert(2, 22);
+        assert_eq!(cache.meta::<f64>(2), None);
+        assert_eq!(cache.meta::<&str>(3), Some(&"three"));
+    }
+
+    #[test]
+    fn debug_dump_lists_header_and_entries() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::new(4);
+        cache.set_clock(clock.clone());
+        cache.insert(1, "a");
+        clock.advance(Duration::from_secs(3));
+        cache.insert(2, "b");
+        cache.get(1);
+        cache.get(5);
+
+        let dump = cache.debug_dump();
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "capacity: 4",
+                "len: 2",
+                "load factor: 0.50",
+                "stats: 1 hits, 1 misses, 0 evictions",
+                "#0 1 => \"a\" (age 3s)",
+                "#1 2 => \"b\" (age 0ns)",
+            ]
+        );
+    }
+
+    #[test]
+    fn get_or_insert_default_at_places_cold_defaults() {
+        let mut cache = LRUCache::new(4);
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        assert_eq!(cache.get_or_insert_default_at(7, 5), &0);
+        assert_eq!(cache.get_or_insert_default_at(8, 2), &0);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 1, 8, 7]);
+
+        cache.insert(3, 30);
+        cache.insert(4, 40);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![4, 3, 2, 1]);
+        assert_eq!(cache.get_or_insert_default_at(1, 3), &10);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![1, 4, 3, 2]);
+    }
+
+    #[test]
+    fn recency_windows_slide_from_mru() {
+        let mut cache = LRUCache::new(5);
+        for i in 0..5 {
+            cache.insert(i, i * 10);
+        }
+        let windows: Vec<Vec<_>> = cache
+            .recency_windows(3)
+            .map(|window| window.into_iter().map(|(&k, &v)| (k, v)).collect())
+            .collect();
+        assert_eq!(
+            windows,
+            vec![
+                vec![(4, 40), (3, 30), (2, 20)],
+                vec![(3, 30), (2, 20), (1, 10)],
+                vec![(2, 20), (1, 10), (0, 0)],
+            ]
+        );
+        assert_eq!(cache.recency_windows(6).count(), 0);
+        assert_eq!(cache.recency_windows(0).count(), 0);
+    }
+
+    #[test]
+    fn move_to_rank_repositions_entries() {
+        let mut cache = LRUCache::new(5);
+        for i in 0..5 {
+            cache.insert(i, i);
+        }
+        assert!(cache.move_to_rank(2, 0));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 4, 3, 1, 0]);
+        assert!(cache.move_to_rank(4, 99));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 3, 1, 0, 4]);
+        assert!(cache.move_to_rank(0, 1));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 0, 3, 1, 4]);
+        assert!(!cache.move_to_rank(7, 0));
+    }
+
+    #[test]
+    fn vacant_entry_insert_with_ttl_overrides_default() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::new(4);
+        cache.set_clock(clock.clone());
+        cache.set_ttl(Some(Duration::from_secs(60)));
+        if let Entry::Vacant(entry) = cache.entry(1) {
+            *entry.insert_with_ttl("short", Duration::from_secs(5)) = "SHORT";
+        }
+        cache.entry(2).or_insert("default");
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(cache.is_expired(1), Some(true));
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get(2), Some(&"default"));
+    }
+
+    #[test]
+    fn evict_fraction_sheds_coldest() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.evict_fraction(0.25), vec![(0, 0)]);
+        assert_eq!(cache.evict_fraction(0.5), vec![(1, 10), (2, 20)]);
+        assert!(cache.evict_fraction(-1.0).is_empty());
+        assert_eq!(cache.evict_fraction(7.0), vec![(3, 30)]);
+        assert_eq!(cache.stats().evictions, 4);
+    }
+
+    #[test]
+    fn insert_if_newer_guards_by_timestamp() {
+        let clock = MockClock::new();
+        let older = clock.now();
+        let newer = older + Duration::from_secs(5);
+        let mut cache = LRUCache::new(2);
+        cache.set_clock(clock.clone());
+
+        assert!(cache.insert_if_newer(1, "new", newer));
+        assert!(!cache.insert_if_newer(1, "old", older));
+        assert!(!cache.insert_if_newer(1, "same", newer));
+        assert_eq!(cache.peek(1), Some(&"new"));
+        assert!(cache.insert_if_newer(1, "newest", newer + Duration::from_secs(1)));
+        assert_eq!(cache.peek(1), Some(&"newest"));
+    }
+
+    #[test]
+    fn evict_coldest_group_takes_whole_group() {
+        let mut cache = LRUCache::new(6);
+        for (key, session) in [(1, 'a'), (2, 'b'), (3, 'a'), (4, 'c'), (5, 'a'), (6, 'b')] {
+            cache.insert(key, session);
+        }
+        let evicted = cache.evict_coldest_group(|_, &session| session);
+        assert_eq!(evicted, vec![(1, 'a'), (3, 'a'), (5, 'a')]);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![6, 4, 2]);
+        assert_eq!(cache.stats().evictions, 3);
+    }
+
+    #[test]
+    fn prefetch_reserves_slot_for_later_insert() {
+        let mut cache = LRUCache::new(4);
+        cache.insert(1, "a");
+        cache.prefetch(9);
+        cache.insert(2, "b");
+        cache.insert(3, "c");
+        assert_eq!(cache.get(9), None);
+        assert_eq!(cache.recency_rank(9), None);
+        assert_eq!(cache.recency_rank(1), Some(2));
+
+        cache.insert(9, "z");
+        assert_eq!(cache.order.iter().copied().collect::<Vec<_>>(), vec![3, 2, 9, 1]);
+        assert_eq!(cache.peek(9), Some(&"z"));
+        assert_eq!(cache.get(9), Some(&"z"));
+    }
+
+    #[test]
+    fn unfilled_prefetch_is_evicted_when_cold() {
+        let mut cache = LRUCache::new(3);
+        cache.prefetch(9);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        cache.insert(3, "c");
+        assert_eq!(cache.recency_rank(9), None);
+        assert_eq!(cache.order.iter().copied().collect::<Vec<_>>(), vec![3, 2, 1]);
+        assert_eq!(cache.stats().evictions, 0);
+
+        cache.prefetch(8);
+        assert_eq!(cache.pop_lru(), Some((2, "b")));
+        assert_eq!(cache.drain_lru(5), vec![(3, "c")]);
+        assert!(cache.order.is_empty());
+    }
+
+    #[test]
+    fn ordered_pairs_agree_on_direction() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        cache.get(1);
+        let pairs = cache.ordered_pairs();
+        let mut rev = cache.ordered_pairs_rev();
+        assert_eq!(pairs[0], (1, 10));
+        assert_eq!(rev[0], (0, 0));
+        rev.reverse();
+        assert_eq!(pairs, rev);
+        assert_eq!(cache.pop_lru(), Some((0, 0)));
+    }
+
+    #[test]
+    fn get_with_neighbors_reports_former_flanks() {
+        let mut cache = LRUCache::new(5);
+        for i in 0..5 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.get_with_neighbors(2), Some((&20, vec![3, 1])));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 4, 3, 1, 0]);
+        assert_eq!(cache.get_with_neighbors(0), Some((&0, vec![1])));
+        assert_eq!(cache.get_with_neighbors(7), None);
+    }
+
+    #[test]
+    fn take_returns_values_from_each_end() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.take_lru(), Some(0));
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.take_mru(), Some(30));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.pop_mru(), Some((2, 20)));
+        assert_eq!(cache.take_lru(), Some(10));
+        assert!(cache.is_empty());
+        assert_eq!(cache.take_mru(), None);
+    }
+
+    #[test]
+    fn segmented_stats_split_by_tenant() {
+        let mut cache = LRUCache::new(4).with_segmented_stats(|key: &(char, u32)| key.0);
+        cache.insert(('a', 1), 1);
+        cache.insert(('b', 1), 1);
+        cache.get(('a', 1));
+        cache.get(('a', 1));
+        cache.get(('a', 2));
+        cache.get(('b', 2));
+        cache.get(('b', 3));
+
+        let segments = cache.segmented_stats::<char>();
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[&'a'].hits, segments[&'a'].misses), (2, 1));
+        assert_eq!((segments[&'b'].hits, segments[&'b'].misses), (0, 2));
+        assert!(cache.segmented_stats::<String>().is_empty());
+        assert!(LRUCache::<u8, u8>::new(1).segmented_stats::<char>().is_empty());
+
+        let mut clone = cache.clone();
+        clone.get(('a', 1));
+        assert_eq!(clone.segmented_stats::<char>(), segments);
+    }
+
+    #[test]
+    fn into_frozen_keeps_items_and_order() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        cache.get(1);
+        cache.invalidate(2);
+        let frozen = cache.into_frozen();
+
+        let read = |frozen: &FrozenCache<i32, i32>| frozen.get(1).copied();
+        assert_eq!(read(&frozen), Some(10));
+        assert_eq!(frozen.get(2), None);
+        assert!(frozen.contains_key(3));
+        assert_eq!(frozen.len(), 3);
+        let items: Vec<_> = frozen.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(items, vec![(1, 10), (3, 30), (0, 0)]);
+    }
+
+    #[test]
+    fn slru_lowering_protected_ratio_demotes_coldest() {
+        let mut cache = SLRUCache::new(10, 0.5);
+        for i in 1..=5 {
+            cache.insert(i, i * 10);
+            assert_eq!(cache.get(i), Some(&(i * 10)));
+        }
+        cache.insert(6, 60);
+        cache.insert(7, 70);
+        assert_eq!((cache.protected_len(), cache.probation_len()), (5, 2));
+
+        cache.set_protected_ratio(0.2);
+        assert_eq!((cache.protected_len(), cache.probation_len()), (2, 5));
+        assert!(cache.is_protected(5) && cache.is_protected(4));
+        assert!((1..=3).all(|key| !cache.is_protected(key)));
+        assert_eq!(cache.len(), 7);
+        assert_eq!(cache.probation.keys().copied().collect::<Vec<_>>(), vec![3, 2, 1, 7, 6]);
+
+        cache.set_protected_ratio(0.9);
+        assert_eq!((cache.protected_len(), cache.probation_len()), (6, 1));
+        assert_eq!(cache.protected.keys().copied().collect::<Vec<_>>(), vec![5, 4, 3, 2, 1, 7]);
+    }
+
+    #[test]
+    fn get_or_insert_with_if_room_skips_factory_when_full() {
+        let mut cache = LRUCache::new(2);
+        let mut calls = 0;
+        assert_eq!(cache.get_or_insert_with_if_room(1, || { calls += 1; 10 }), Some(&10));
+        assert_eq!(cache.get_or_insert_with_if_room(2, || { calls += 1; 20 }), Some(&20));
+        assert_eq!(cache.get_or_insert_with_if_room(3, || { calls += 1; 30 }), None);
+        assert_eq!(cache.get_or_insert_with_if_room(1, || { calls += 1; 11 }), Some(&10));
+        assert_eq!(calls, 2);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn distinct_value_count_ignores_duplicates() {
+        let mut cache = LRUCache::new(5);
+        assert_eq!(cache.distinct_value_count(), 0);
+        for (key, value) in [(1, "x"), (2, "y"), (3, "x"), (4, "z"), (5, "y")] {
+            cache.insert(key, value);
+        }
+        assert_eq!(cache.distinct_value_count(), 3);
+        cache.insert(6, "w");
+        assert_eq!(cache.distinct_value_count(), 4);
+    }
+
+    #[test]
+    fn drain_expired_returns_expired_entries() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::new(5);
+        cache.set_clock(clock.clone());
+        cache.insert_with_ttl(1, "a", Duration::from_secs(5));
+        cache.insert_with_ttl(2, "b", Duration::from_secs(20));
+        cache.insert_with_ttl(3, "c", Duration::from_secs(3));
+        cache.insert(4, "d");
+        cache.insert_with_ttl(5, "e", Duration::from_secs(30));
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(cache.drain_expired(), vec![(1, "a"), (3, "c")]);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![5, 4, 2]);
+        assert!(cache.drain_expired().is_empty());
+    }
+
+    #[test]
+    fn recompute_weights_evicts_after_values_mut() {
+        let mut cache = LRUCache::bounded_by(Bound::Weight(10, |_, value: &String| value.len()));
+        cache.insert(1, "aa".to_string());
+        cache.insert(2, "bb".to_string());
+        cache.insert(3, "cc".to_string());
+        assert_eq!(cache.total_weight(), 6);
+        for value in cache.values_mut() {
+            value.push_str("xx");
+        }
+        assert_eq!(cache.recompute_weights(), vec![(1, "aaxx".to_string())]);
+        assert_eq!(cache.total_weight(), 8);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![3, 2]);
+        assert!(LRUCache::<u8, u8>::new(2).recompute_weights().is_empty());
+    }
+
+    #[test]
+    fn on_evict_serialized_receives_bytes() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Arc::clone(&written);
+        let mut cache = LRUCache::new(2);
+        cache.set_on_evict_serialized(move |key, bytes| {
+            recorder.lock().unwrap().push((key, bytes));
+        });
+        cache.insert(1, 0x0102u16);
+        cache.insert(2, 0x0304u16);
+        cache.insert(3, 0x0506u16);
+        assert!(written.lock().unwrap().is_empty());
+
+        cache.set_value_serializer(|value: &u16| value.to_be_bytes().to_vec());
+        cache.insert(4, 0x0708u16);
+        assert_eq!(*written.lock().unwrap(), vec![(2, vec![0x03, 0x04])]);
+        assert_eq!(cache.last_evicted(), Some(&(2, 0x0304)));
+    }
+
+    #[test]
+    fn sharded_aggregate_stats_counts_all_lookups() {
+        let cache = ShardedCache::new(4, 16);
+        for key in 0..32u32 {
+            cache.insert(key, key);
+        }
+        std::thread::scope(|scope| {
+            for thread in 0..8u32 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    for i in 0..500u32 {
+                        cache.get_cloned((thread * 500 + i) % 64);
+                        if i % 10 == 0 {
+                            cache.insert(i % 48, i);
+                        }
+                    }
+                });
+            }
+        });
+        let stats = cache.aggregate_stats();
+        assert_eq!(stats.hits + stats.misses, 8 * 500);
+        assert!(stats.hits > 0 && stats.misses > 0);
+        assert_eq!(cache.shard_count(), 4);
+    }
+
+    #[test]
+    fn map_skips_stale_items() {
+        let mut cache = LRUCache::new(3);
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        cache.insert(3, 30);
+        cache.invalidate(2);
+        let mapped = cache.map(|&key, &value| (key, value + 1));
+        assert_eq!(mapped.keys().copied().collect::<Vec<_>>(), vec![3, 1]);
+        assert_eq!(mapped.peek(2), None);
+    }
+
+    #[test]
+    fn merge_newest_wins_skips_timestamps_of_rejected_keys() {
+        let clock = MockClock::new();
+        let mut other = LRUCache::new(2);
+        other.set_clock(clock.clone());
+        other.insert(1, "a");
+        let mut cache = LRUCache::new(2);
+        cache.set_clock(clock.clone());
+        cache.set_admission_probability(0.0);
+        cache.merge_newest_wins(other);
+        assert_eq!(cache.peek(1), None);
+        assert!(!cache.inserted_at.contains_key(&1));
+
+        cache.set_admission_probability(1.0);
+        assert!(cache.insert_if_newer(1, "b", clock.now()));
+    }
+
+    #[test]
+    fn export_state_skips_stale_and_import_bypasses_admission() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i);
+        }
+        cache.invalidate(1);
+        let state = cache.export_state();
+        assert_eq!(state, vec![(3, 3, 0), (2, 2, 1), (0, 0, 2)]);
+
+        let mut imported = LRUCache::with_seed(4, 1);
+        imported.set_admission_probability(0.0);
+        imported.import_state(state);
+        assert_eq!(imported.keys().copied().collect::<Vec<_>>(), vec![3, 2, 0]);
+        assert_eq!(imported.peek(1), None);
+    }
+
+    #[test]
+    fn get_or_compute_batch_reports_evicted_requests() {
+        let mut cache = LRUCache::bounded_by(Bound::Weight(10, |_, value: &usize| *value));
+        cache.insert(1, 4);
+        let values = cache.get_or_compute_batch(&[1, 2, 3], |missing| vec![4; missing.len()]);
+        assert_eq!(values, vec![None, Some(&4), Some(&4)]);
+    }
+
+    #[test]
+    fn replace_all_bypasses_admission() {
+        let mut cache = LRUCache::with_seed(4, 3);
+        cache.insert(9, 90);
+        cache.set_admission_probability(0.0);
+        assert_eq!(cache.replace_all([(1, 10), (2, 20), (3, 30)]), vec![(9, 90)]);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn clone_filtered_skips_stale_items() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::new(4);
+        cache.set_clock(clock.clone());
+        cache.insert_with_ttl(1, 10, Duration::from_secs(5));
+        cache.insert(2, 20);
+        cache.insert(3, 30);
+        cache.invalidate(3);
+        clock.advance(Duration::from_secs(5));
+
+        let copy = cache.clone_filtered(4, |_, _| true);
+        assert_eq!(copy.keys().copied().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(copy.peek(1), None);
+    }
+
+    #[test]
+    fn partition_drops_stale_items() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i);
+        }
+        cache.invalidate(2);
+        let seen = RefCell::new(Vec::new());
+        let (even, odd) = cache.partition(|&key, _| {
+            seen.borrow_mut().push(key);
+            key % 2 == 0
+        });
+        assert!(!seen.borrow().contains(&2));
+        assert_eq!(even.keys().copied().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(odd.keys().copied().collect::<Vec<_>>(), vec![3, 1]);
+    }
+
+    #[test]
+    fn rollback_ignores_admission() {
+        let mut cache = LRUCache::with_seed(8, 1);
+        for i in 0..6 {
+            cache.insert(i, i * 10);
+        }
+        let cp = cache.checkpoint();
+        cache.set_admission_probability(0.1);
+        cache.clear();
+        cache.rollback(&cp);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![5, 4, 3, 2, 1, 0]);
+        assert_eq!(cache.stats().evictions, 0);
+
+        cache.resize(3);
+        cache.rollback(&cp);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![5, 4, 3]);
+    }
+
+    #[test]
+    fn try_insert_all_counts_growth() {
+        let mut cache = LRUCache::new(2).with_growth_policy(GrowthPolicy::Linear { step: 2, max: 6 });
+        cache.insert(1, 1);
+        assert_eq!(cache.try_insert_all(vec![(2, 2), (3, 3), (4, 4), (5, 5)]), Ok(()));
+        assert_eq!(cache.capacity(), 6);
+        assert_eq!(cache.keys().count(), 5);
+        assert!(cache.try_insert_all(vec![(6, 6), (7, 7)]).is_err());
+        assert_eq!(cache.keys().count(), 5);
+    }
+
+    #[test]
+    fn swap_with_carries_capacity_range() {
+        let mut ranged = LRUCache::builder()
+            .capacity(4)
+            .min_capacity(2)
+            .max_capacity(6)
+            .build()
+            .unwrap();
+        ranged.insert(1, 1);
+        let mut plain = LRUCache::new(20);
+        plain.insert(2, 2);
+
+        ranged.swap_with(&mut plain);
+        assert!(ranged.resize(50));
+        assert_eq!(ranged.capacity(), 50);
+        assert!(plain.resize(50));
+        assert_eq!(plain.capacity(), 6);
+    }
+
+    #[test]
+    fn insert_if_newer_treats_stale_as_absent() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::new(2);
+        cache.set_clock(clock.clone());
+        let newer = clock.now() + Duration::from_secs(5);
+        assert!(cache.insert_if_newer(1, "new", newer));
+        cache.invalidate(1);
+        assert!(cache.insert_if_newer(1, "old", clock.now()));
+        assert_eq!(cache.peek(1), Some(&"old"));
+    }
+
+    #[test]
+    fn ranks_skip_prefetch_placeholders() {
+        let mut cache = LRUCache::new(5);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        cache.prefetch(9);
+        cache.insert(3, "c");
+        assert_eq!(cache.order.iter().copied().collect::<Vec<_>>(), vec![3, 9, 2, 1]);
+
+        cache.insert_at_rank(4, "d", 1);
+        assert_eq!(cache.order.iter().copied().collect::<Vec<_>>(), vec![3, 9, 4, 2, 1]);
+        assert_eq!(cache.recency_rank(4), Some(1));
+        assert_eq!(cache.get_with_rank(4), Some((&"d", 0)));
+
+        assert!(cache.move_to_rank(3, 2));
+        assert_eq!(cache.recency_rank(3), Some(2));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![4, 2, 3, 1]);
+    }
+
+    #[test]
+    fn get_or_insert_with_if_room_respects_weight_budget() {
+        let mut cache =
+            LRUCache::<usize, &str>::bounded_by(Bound::Weight(4, |_, value| value.len()));
+        assert_eq!(cache.get_or_insert_with_if_room(1, || "ab"), Some(&"ab"));
+        assert_eq!(cache.get_or_insert_with_if_room(2, || "cd"), Some(&"cd"));
+        let mut called = false;
+        assert_eq!(cache.get_or_insert_with_if_room(3, || { called = true; "e" }), None);
+        assert!(!called);
+        assert_eq!(cache.stats().evictions, 0);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 1]);
+    }
+
+    #[test]
+    fn on_evict_serialized_covers_every_eviction() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Arc::clone(&written);
+        let mut cache =
+            LRUCache::<u8, &str>::bounded_by(Bound::Weight(4, |_, value| value.len()));
+        cache.set_value_serializer(|value: &&str| value.as_bytes().to_vec());
+        cache.set_on_evict_serialized(move |key, bytes| {
+            recorder.lock().unwrap().push((key, bytes));
+        });
+        cache.insert(1, "ab");
+        cache.insert(2, "cd");
+        cache.get_or_insert_with_position(3, InsertPos::Back, || "ef");
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(*written.lock().unwrap(), vec![(1, b"ab".to_vec())]);
+        assert_eq!(cache.last_evicted(), Some(&(1, "ab")));
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Arc::clone(&written);
+        let mut cache = LRUCache::new(2);
+        cache.set_value_serializer(|value: &u8| vec![*value]);
+        cache.set_on_evict_serialized(move |key, bytes| {
+            recorder.lock().unwrap().push((key, bytes));
+        });
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        assert_eq!(cache.make_room_for(2), vec![(1, 10), (2, 20)]);
+        assert_eq!(cache.stats().evictions, 2);
+        assert_eq!(*written.lock().unwrap(), vec![(1, vec![10]), (2, vec![20])]);
+    }
+
+    #[test]
+    fn merge_newest_wins_treats_stale_as_absent() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::new(3);
+        cache.set_clock(clock.clone());
+        let mut other = LRUCache::new(3);
+        other.set_clock(clock.clone());
+
+        other.insert(1, "stale");
+        other.invalidate(1);
+        other.insert(2, "live");
+        clock.advance(Duration::from_secs(1));
+        cache.insert(2, "newer but stale");
+        cache.invalidate(2);
+
+        cache.merge_newest_wins(other);
+        assert_eq!(cache.peek(1), None);
+        assert_eq!(cache.peek(2), Some(&"live"));
+    }
+
+    #[test]
+    fn insert_evicting_matching_records_and_grows() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Arc::clone(&written);
+        let mut cache = LRUCache::<usize, &str>::new(2);
+        cache.set_value_serializer(|value: &&str| value.as_bytes().to_vec());
+        cache.set_on_evict_serialized(move |key, bytes| {
+            recorder.lock().unwrap().push((key, bytes));
+        });
+        cache.insert(1, "di");
+        cache.insert(2, "cl");
+        let clean = |_: &usize, value: &&str| *value == "cl";
+        assert_eq!(cache.insert_evicting_matching(3, "di", clean), Ok(Some((2, "cl"))));
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.last_evicted(), Some(&(2, "cl")));
+        assert_eq!(*written.lock().unwrap(), vec![(2, b"cl".to_vec())]);
+
+        let mut cache = LRUCache::new(2).with_growth_policy(GrowthPolicy::Double { max: 8 });
+        cache.insert(1, "di");
+        cache.insert(2, "di");
+        assert_eq!(cache.insert_evicting_matching(3, "di", clean), Ok(None));
+        assert_eq!(cache.capacity(), 4);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn insert_evicting_matching_rejects_weight_bounded() {
+        let mut cache =
+            LRUCache::<usize, &str>::bounded_by(Bound::Weight(4, |_, value| value.len()));
+        cache.insert(1, "di");
+        cache.insert(2, "cl");
+        let clean = |_: &usize, value: &&str| *value == "cl";
+        assert_eq!(cache.insert_evicting_matching(3, "xx", clean), Err((3, "xx")));
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 1]);
+    }
+
+    #[test]
+    fn insert_at_rank_grows_instead_of_evicting() {
+        let mut cache = LRUCache::new(2).with_growth_policy(GrowthPolicy::Double { max: 8 });
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.insert_at_rank(3, "c", 1), None);
+        assert_eq!(cache.capacity(), 4);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 3, 1]);
+        assert_eq!(cache.stats().evictions, 0);
+    }
+
+    #[test]
+    fn rollback_keeps_timestamps() {
+        let clock = MockClock::new();
+        let mut cache = LRUCache::new(3);
+        cache.set_clock(clock.clone());
+        cache.set_ttl(Some(Duration::from_secs(10)));
+        cache.insert(1, "a");
+        let stamped = clock.now() + Duration::from_secs(1);
+        cache.insert_if_newer(2, "b", stamped);
+        let cp = cache.checkpoint();
+
+        clock.advance(Duration::from_secs(7));
+        cache.rollback(&cp);
+        assert_eq!(cache.inserted_at.get(&2), Some(&stamped));
+        clock.advance(Duration::from_secs(7));
+        assert_eq!(cache.peek(1), None);
+    }
+
+    #[test]
+    fn export_ranks_agrees_with_export_state() {
+        let mut cache = LRUCache::new(4);
+        for i in 0..4 {
+            cache.insert(i, i);
+        }
+        cache.invalidate(2);
+        assert_eq!(cache.export_ranks(0..4), vec![(3, 3), (1, 1), (0, 0)]);
+        let ranked: Vec<(usize, usize)> =
+            cache.export_state().into_iter().map(|(key, _, rank)| (key, rank)).collect();
+        assert_eq!(ranked, vec![(3, 0), (1, 1), (0, 2)]);
+    }
+
+    #[test]
+    fn merge_sum_ignores_stale_counters() {
+        let mut cache = LRUCache::new(3);
+        cache.insert(1, 100);
+        cache.insert(2, 5);
+        cache.invalidate(1);
+        let mut other = LRUCache::new(3);
+        other.insert(1, 1);
+        other.insert(2, 7);
+        other.invalidate(2);
+
+        cache.merge_sum(other);
+        assert_eq!(cache.peek(1), Some(&1));
+        assert_eq!(cache.peek(2), Some(&5));
+    }
+
+    #[test]
+    fn get_or_insert_default_at_grows_instead_of_evicting() {
+        let mut cache =
+            LRUCache::<usize, u8>::new(2).with_growth_policy(GrowthPolicy::Double { max: 8 });
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        assert_eq!(cache.get_or_insert_default_at(3, 10), &0);
+        assert_eq!(cache.capacity(), 4);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn prefetch_grows_instead_of_evicting() {
+        let mut cache = LRUCache::new(2).with_growth_policy(GrowthPolicy::Double { max: 8 });
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        cache.prefetch(9);
+        assert_eq!(cache.capacity(), 4);
+        assert_eq!(cache.peek(1), Some(&"a"));
+        assert_eq!(cache.order.iter().copied().collect::<Vec<_>>(), vec![9, 2, 1]);
     }
 }
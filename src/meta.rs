@@ -0,0 +1,58 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use crate::LRUCache;
+
+/// The metadata attached to items with `LRUCache::set_meta`.
+/// Like callbacks, metadata isn't carried over to clones of a cache,
+/// since it is stored type-erased and can't be cloned.
+pub(crate) struct MetaMap<K>(pub(crate) HashMap<K, Box<dyn Any + Send>>);
+
+impl<K> Default for MetaMap<K> {
+    fn default() -> Self {
+        MetaMap(HashMap::new())
+    }
+}
+
+impl<K> Clone for MetaMap<K> {
+    fn clone(&self) -> Self {
+        MetaMap::default()
+    }
+}
+
+impl<K> fmt::Debug for MetaMap<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} entries", self.0.len())
+    }
+}
+
+impl<K, V> LRUCache<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    /// Attaches `meta` to the item for `key`, replacing any metadata it
+    /// had. The metadata stays until the item leaves the cache; updating
+    /// the value keeps it. Doesn't change the recency order.
+    ///
+    /// Returns `false` if the `key` isn't in the cache.
+    pub fn set_meta<M: Any + Send>(&mut self, key: K, meta: M) -> bool {
+        if !self.storage.contains_key(&key) {
+            return false;
+        }
+        self.meta.0.insert(key, Box::new(meta));
+        true
+    }
+
+    /// Returns the metadata of the item for `key`, if it has any of type `M`.
+    pub fn meta<M: Any>(&self, key: K) -> Option<&M> {
+        self.meta.0.get(&key)?.downcast_ref()
+    }
+
+    /// Like `iter`, but also yields the metadata of each item,
+    /// if it has any of type `M`.
+    pub fn iter_with_meta<M: Any>(&self) -> impl Iterator<Item = (&K, &V, Option<&M>)> + '_ {
+        self.iter().map(|(key, value)| (key, value, self.meta(*key)))
+    }
+}
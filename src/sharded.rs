@@ -0,0 +1,77 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{CacheStats, LRUCache};
+
+/// A cache split into independently locked `LRUCache` shards, so that
+/// threads working on different shards don't wait for each other. Each key
+/// always goes to the same shard, and recency is tracked per shard.
+#[derive(Debug)]
+pub struct ShardedCache<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    shards: Vec<Mutex<LRUCache<K, V>>>,
+    hasher: RandomState,
+}
+
+impl<K, V> ShardedCache<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    /// Creates a new `ShardedCache` with `shards` shards (at least one),
+    /// each holding up to `capacity_per_shard` items.
+    pub fn new(shards: usize, capacity_per_shard: usize) -> Self {
+        ShardedCache {
+            shards: (0..shards.max(1))
+                .map(|_| Mutex::new(LRUCache::new(capacity_per_shard)))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Returns the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Inserts an item into the shard of `key`; see `LRUCache::insert`.
+    pub fn insert(&self, key: K, value: V) -> Option<K> {
+        self.shard(key).insert(key, value)
+    }
+
+    /// Returns a copy of the value corresponding to the `key`, making it
+    /// the most recently used item of its shard; see `LRUCache::get`.
+    pub fn get_cloned(&self, key: K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.shard(key).get(key).cloned()
+    }
+
+    /// Returns the hit, miss and eviction counters summed over all shards.
+    /// The shards are locked one at a time, always in the same order, so
+    /// this never holds more than one lock and can't deadlock. The figures
+    /// of different shards may be taken at slightly different times.
+    pub fn aggregate_stats(&self) -> CacheStats {
+        let mut total = CacheStats::default();
+        for shard in &self.shards {
+            let stats = lock(shard).stats();
+            total.hits += stats.hits;
+            total.misses += stats.misses;
+            total.evictions += stats.evictions;
+        }
+        total
+    }
+
+    fn shard(&self, key: K) -> MutexGuard<'_, LRUCache<K, V>> {
+        let index = self.hasher.hash_one(key) % self.shards.len() as u64;
+        lock(&self.shards[index as usize])
+    }
+}
+
+// A shard stays usable even if a thread panicked while holding its lock.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
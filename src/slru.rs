@@ -0,0 +1,137 @@
+use std::hash::Hash;
+
+use crate::LRUCache;
+
+/// A segmented LRU cache. New items land in a probationary segment and
+/// move to a protected segment when they are looked up again, so a burst
+/// of one-off items can only flush the probationary segment. Items pushed
+/// out of the protected segment get another chance in the probationary one.
+#[derive(Debug, Clone)]
+pub struct SLRUCache<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    pub(crate) probation: LRUCache<K, V>,
+    pub(crate) protected: LRUCache<K, V>,
+    capacity: usize,
+}
+
+impl<K, V> SLRUCache<K, V>
+where
+    K: Eq + PartialEq + Copy + Hash,
+{
+    /// Creates a new `SLRUCache` holding up to `capacity` items, of which
+    /// the share `protected_ratio` is set aside for the protected segment.
+    /// See `set_protected_ratio`.
+    pub fn new(capacity: usize, protected_ratio: f64) -> Self {
+        let protected = protected_capacity(capacity, protected_ratio);
+        SLRUCache {
+            probation: LRUCache::new(capacity - protected),
+            protected: LRUCache::new(protected),
+            capacity,
+        }
+    }
+
+    /// Returns the maximum number of items the cache can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of items in the cache.
+    pub fn len(&self) -> usize {
+        self.probation.len() + self.protected.len()
+    }
+
+    /// Returns whether the cache holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of items in the probationary segment.
+    pub fn probation_len(&self) -> usize {
+        self.probation.len()
+    }
+
+    /// Returns the number of items in the protected segment.
+    pub fn protected_len(&self) -> usize {
+        self.protected.len()
+    }
+
+    /// Returns whether the item for `key` is in the protected segment.
+    pub fn is_protected(&self, key: K) -> bool {
+        self.protected.peek(key).is_some()
+    }
+
+    /// Inserts an item into the cache. A new item becomes the most recently
+    /// used one of the probationary segment, evicting the least recently
+    /// used one there if it is full. An existing item is updated in place
+    /// in its segment and becomes the most recently used one of it.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.protected.peek(key).is_some() {
+            self.protected.insert(key, value);
+        } else {
+            self.probation.insert(key, value);
+        }
+    }
+
+    /// Returns a reference to the value corresponding to the `key`.
+    /// An item found in the probationary segment moves to the protected
+    /// one, whose least recently used item moves back to probation if it
+    /// is full.
+    pub fn get(&mut self, key: K) -> Option<&V> {
+        if self.protected.capacity() == 0 {
+            return self.probation.get(key);
+        }
+        if self.protected.peek(key).is_none() {
+            let value = self.probation.remove_entry(key)?;
+            if self.protected.is_full() {
+                if let Some((demoted, value)) = self.protected.pop_lru() {
+                    self.probation.insert(demoted, value);
+                }
+            }
+            self.protected.insert(key, value);
+        }
+        self.protected.get(key)
+    }
+
+    /// Returns a reference to the value corresponding to the `key`,
+    /// without moving it within or between the segments.
+    pub fn peek(&self, key: K) -> Option<&V> {
+        self.protected.peek(key).or_else(|| self.probation.peek(key))
+    }
+
+    /// Changes the share of the capacity set aside for the protected
+    /// segment; `ratio` is clamped to the range from 0 to 1, but the
+    /// probationary segment always keeps at least one slot. If the protected
+    /// segment shrinks, its least recently used items are demoted to the
+    /// front of the probationary segment. If it grows, the most recently
+    /// used items from probation that no longer fit there are promoted to
+    /// the back of the protected segment. Nothing is evicted either way.
+    pub fn set_protected_ratio(&mut self, ratio: f64) {
+        let protected = protected_capacity(self.capacity, ratio);
+        let probation = self.capacity - protected;
+        let mut demoted = Vec::new();
+        while self.protected.len() > protected {
+            demoted.extend(self.protected.pop_lru());
+        }
+        let mut promoted = Vec::new();
+        while self.probation.len() + demoted.len() > probation {
+            promoted.extend(self.probation.pop_mru());
+        }
+        self.protected.resize(protected);
+        self.probation.resize(probation);
+        for (key, value) in demoted {
+            self.probation.insert(key, value);
+        }
+        for (key, value) in promoted {
+            self.protected.insert_at_rank(key, value, usize::MAX);
+        }
+    }
+}
+
+// The size of the protected segment for `ratio` of `capacity`,
+// leaving at least one slot for probation.
+fn protected_capacity(capacity: usize, ratio: f64) -> usize {
+    let protected = (capacity as f64 * ratio.clamp(0.0, 1.0)) as usize;
+    protected.min(capacity.saturating_sub(1))
+}
@@ -0,0 +1,180 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crate::hook::Hook;
+
+/// Counters of what happened to a cache; see `LRUCache::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// Lookups that found their key.
+    pub hits: u64,
+    /// Lookups that didn't find their key.
+    pub misses: u64,
+    /// Items evicted to make room for others.
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Returns the share of lookups that were hits,
+    /// or 0 if there were no lookups yet.
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// The stats of a cache at some point in time; see
+/// `LRUCache::stats_snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// The counters when the snapshot was taken.
+    pub stats: CacheStats,
+    /// When the snapshot was taken, according to the cache's clock.
+    pub taken_at: Instant,
+}
+
+/// How the stats changed between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatsDelta {
+    /// Hits in between.
+    pub hits: u64,
+    /// Misses in between.
+    pub misses: u64,
+    /// Evictions in between.
+    pub evictions: u64,
+    /// The time between the two snapshots.
+    pub elapsed: Duration,
+}
+
+impl StatsSnapshot {
+    /// Returns what happened between `earlier` and this snapshot.
+    pub fn since(&self, earlier: &StatsSnapshot) -> StatsDelta {
+        StatsDelta {
+            hits: self.stats.hits.saturating_sub(earlier.stats.hits),
+            misses: self.stats.misses.saturating_sub(earlier.stats.misses),
+            evictions: self.stats.evictions.saturating_sub(earlier.stats.evictions),
+            elapsed: self.taken_at.saturating_duration_since(earlier.taken_at),
+        }
+    }
+}
+
+/// Keeps the data for `LRUCache::reuse_histogram`: every key looked up so
+/// far, the most recent first, and how often each reuse distance occurred.
+#[derive(Debug, Clone)]
+pub(crate) struct ReuseTracker<K> {
+    stack: Vec<K>,
+    histogram: Vec<usize>,
+}
+
+impl<K: PartialEq> ReuseTracker<K> {
+    pub(crate) fn new() -> Self {
+        ReuseTracker {
+            stack: Vec::new(),
+            histogram: Vec::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, key: K) {
+        if let Some(distance) = self.stack.iter().position(|element| *element == key) {
+            self.stack.remove(distance);
+            if self.histogram.len() <= distance {
+                self.histogram.resize(distance + 1, 0);
+            }
+            self.histogram[distance] += 1;
+        }
+        self.stack.insert(0, key);
+    }
+
+    pub(crate) fn histogram(&self) -> &[usize] {
+        &self.histogram
+    }
+}
+
+type Counters = Box<dyn Any + Send>;
+type Recorder<K> = dyn FnMut(&mut Counters, &K, bool) + Send;
+
+/// Keeps the data for `LRUCache::segmented_stats`: a `HashMap<S, CacheStats>`,
+/// stored type-erased since the cache isn't generic over the segment type
+/// `S`, and the closure that knows `S` and records lookups into it. Clones
+/// keep the counters so far, but like callbacks the recorder is left out.
+pub(crate) struct SegmentStats<K> {
+    counters: Option<Counters>,
+    clone_counters: Option<fn(&Counters) -> Counters>,
+    record: Hook<Recorder<K>>,
+}
+
+impl<K> SegmentStats<K> {
+    pub(crate) fn set<S, F>(&mut self, segment_of: F)
+    where
+        S: Eq + Hash + Clone + Send + 'static,
+        F: Fn(&K) -> S + Send + 'static,
+    {
+        self.counters = Some(Box::new(HashMap::<S, CacheStats>::new()));
+        self.clone_counters = Some(|counters| Box::new(downcast::<S>(counters).clone()));
+        self.record.set(Box::new(move |counters, key, hit| {
+            let Some(counters) = counters.downcast_mut::<HashMap<S, CacheStats>>() else {
+                return;
+            };
+            let stats = counters.entry(segment_of(key)).or_default();
+            if hit {
+                stats.hits += 1;
+            } else {
+                stats.misses += 1;
+            }
+        }));
+    }
+
+    pub(crate) fn record(&mut self, key: &K, hit: bool) {
+        if let (Some(counters), Some(record)) = (&mut self.counters, self.record.get_mut()) {
+            record(counters, key, hit);
+        }
+    }
+
+    pub(crate) fn get<S: 'static>(&self) -> Option<&HashMap<S, CacheStats>> {
+        self.counters.as_ref()?.downcast_ref()
+    }
+}
+
+// The counters of `SegmentStats::set`, which are always of this type.
+fn downcast<S: 'static>(counters: &Counters) -> &HashMap<S, CacheStats> {
+    counters
+        .downcast_ref()
+        .expect("segment counters of the type they were created with")
+}
+
+impl<K> Default for SegmentStats<K> {
+    fn default() -> Self {
+        SegmentStats {
+            counters: None,
+            clone_counters: None,
+            record: Hook::default(),
+        }
+    }
+}
+
+impl<K> Clone for SegmentStats<K> {
+    fn clone(&self) -> Self {
+        let counters = match (&self.counters, self.clone_counters) {
+            (Some(counters), Some(clone_counters)) => Some(clone_counters(counters)),
+            _ => None,
+        };
+        SegmentStats {
+            counters,
+            clone_counters: self.clone_counters,
+            record: Hook::default(),
+        }
+    }
+}
+
+impl<K> fmt::Debug for SegmentStats<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.counters.is_some() { "Some(..)" } else { "None" })
+    }
+}